@@ -0,0 +1,78 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use wavetable_synthesizer::{audio::graph::{AudioGraph, MASTER_NODE_ID}, devices::{amplifier::Amplifier, oscillator::WaveTableOscillator, unison::UnisonOscillator}, synthesis::{waveforms::WaveForm, wavetable::WaveTable}};
+
+const SAMPLE_RATE: u32 = 48000;
+const BLOCK_SIZE: usize = 512;
+
+/// `oscillators` saw oscillators mixed through an amplifier into the master output
+fn patch(oscillators: usize, max_block_size: usize) -> AudioGraph {
+    let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, max_block_size);
+    let wavetable = WaveTable::from_waveform(WaveForm::Saw, 128).unwrap();
+
+    let amplifier = graph.add_node(Amplifier::new(0.1));
+    for i in 1..=oscillators {
+        let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, wavetable.clone());
+        oscillator.set_frequency(55.0 * i as f32);
+        oscillator.activate();
+        let oscillator = graph.add_node(oscillator);
+        graph.connect(oscillator, amplifier).unwrap();
+    }
+    graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+    graph
+}
+
+/// One unison oscillator of `voices` voices playing a saw into a stereo master output
+fn unison_patch(voices: usize) -> AudioGraph {
+    let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, BLOCK_SIZE);
+    graph.set_output_channels(2).unwrap();
+    let mut oscillator = UnisonOscillator::new(SAMPLE_RATE, WaveTable::from_waveform(WaveForm::Saw, 2048).unwrap())
+        .with_voices(voices)
+        .with_detune(30.0);
+    oscillator.set_frequency(110.0);
+    oscillator.activate();
+    let oscillator = graph.add_node(oscillator);
+    graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+    graph
+}
+
+fn render_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("graph");
+    group.throughput(Throughput::Elements(BLOCK_SIZE as u64));
+
+    // a max block size of 1 makes every device run once per sample, like the old per sample render path
+    for (name, max_block_size) in [("per_sample", 1), ("block", BLOCK_SIZE)] {
+        for oscillators in [1, 8, 32] {
+            group.bench_with_input(BenchmarkId::new(name, oscillators), &oscillators, |b, oscillators| {
+                let mut graph = patch(*oscillators, max_block_size);
+                let mut buffer = vec![0.0; BLOCK_SIZE];
+                let mut time = 0;
+                b.iter(|| {
+                    graph.process_block(black_box(&mut buffer), time);
+                    time += BLOCK_SIZE as u64;
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+fn unison_voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unison");
+    group.throughput(Throughput::Elements(BLOCK_SIZE as u64));
+
+    for voices in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::new("voices", voices), &voices, |b, voices| {
+            let mut graph = unison_patch(*voices);
+            let mut buffer = vec![0.0; 2 * BLOCK_SIZE];
+            let mut time = 0;
+            b.iter(|| {
+                graph.process_block(black_box(&mut buffer), time);
+                time += BLOCK_SIZE as u64;
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render_paths, unison_voices);
+criterion_main!(benches);
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, BufferSize, Device, FromSample, Host, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, SupportedBufferSize};
+use crossbeam_queue::ArrayQueue;
+
+use crate::{Error, Result, error::AudioError};
+
+use super::device::{select_stream, ConfigRange, DeviceInfo, HostInfo, StreamRequest, StreamSelection};
+
+/// Called from the audio thread with a buffer of interleaved frames to fill
+pub type RenderCallback = Box<dyn FnMut(&mut [f32]) + Send + 'static>;
+
+/// Frames the conversion buffer holds when the device doesn't say, longer buffers are rendered in several parts
+const CONVERSION_FRAMES: usize = 8192;
+
+/// Stream errors kept until they are collected, older ones are dropped first
+const ERROR_CAPACITY: usize = 16;
+
+/// Something that pulls audio from a [`RenderCallback`], usually an output device
+pub trait AudioBackend {
+    fn sample_rate(&self) -> u32;
+
+    /// Channel count of the frames the callback has to fill
+    fn channels(&self) -> usize;
+
+    /// Starts pulling audio from `callback` until [`AudioBackend::stop`] is called
+    fn start(&mut self, callback: RenderCallback) -> Result<()>;
+
+    /// Stops pulling audio and drops the callback, does nothing if the backend isn't running
+    fn stop(&mut self) -> Result<()>;
+
+    /// Returns the errors the stream reported while playing since the last call
+    fn collect_errors(&mut self) -> Vec<Error> {
+        Vec::new()
+    }
+}
+
+/// Plays audio on an output device through cpal
+pub struct CpalBackend {
+    host: Host,
+    device: Device,
+    selection: StreamSelection,
+    stream: Option<Stream>, //kept alive while playing, dropping it stops the output
+    errors: Arc<ArrayQueue<Error>>, //reported by the stream from its own thread
+}
+
+impl CpalBackend {
+    /// Opens the default output device of the default host
+    pub fn new() -> Result<Self> {
+        Self::open(&StreamRequest::default())
+    }
+
+    /// Opens the stream [`select_stream`] picks for `request` out of [`CpalBackend::list_hosts`]
+    pub fn open(request: &StreamRequest) -> Result<Self> {
+        let selection = select_stream(&Self::list_hosts(), request)?;
+        Self::open_selection(selection)
+    }
+
+    pub fn open_selection(selection: StreamSelection) -> Result<Self> {
+        let host_id = cpal::available_hosts().into_iter()
+            .find(|id| id.name() == selection.host)
+            .ok_or(Error::Audio(AudioError::AudioHostNotFound(selection.host.clone())))?;
+        let host = cpal::host_from_id(host_id)
+            .map_err(|err| Error::Audio(AudioError::AudioHostUnavailable(err.to_string())))?;
+        let device = host.output_devices()
+            .map_err(|err| Error::Audio(AudioError::AudioDeviceQuery(err.to_string())))?
+            .find(|device| device.name().is_ok_and(|name| name == selection.device))
+            .ok_or(Error::Audio(AudioError::AudioDeviceNotFound(selection.device.clone())))?;
+        Ok(Self {
+            host,
+            device,
+            selection,
+            stream: None,
+            errors: Arc::new(ArrayQueue::new(ERROR_CAPACITY)),
+        })
+    }
+
+    /// Every available host with its output devices and the configs they support.
+    /// Hosts that can't be opened or queried are skipped, devices whose configs can't be queried are listed without any
+    pub fn list_hosts() -> Vec<HostInfo> {
+        let default_host = cpal::default_host().id();
+        let mut hosts = Vec::new();
+        for id in cpal::available_hosts() {
+            // a broken host, like a JACK server that isn't running, shouldn't keep the others from being used
+            let Ok(host) = cpal::host_from_id(id) else {
+                continue;
+            };
+            let Ok(output_devices) = host.output_devices() else {
+                continue;
+            };
+            let default_device = host.default_output_device().and_then(|device| device.name().ok());
+            let mut devices = Vec::new();
+            for device in output_devices {
+                let Ok(name) = device.name() else {
+                    continue;
+                };
+                let configs = match device.supported_output_configs() {
+                    Ok(configs) => configs.map(|config| ConfigRange {
+                        channels: config.channels(),
+                        min_sample_rate: config.min_sample_rate().0,
+                        max_sample_rate: config.max_sample_rate().0,
+                        buffer_size: match config.buffer_size() {
+                            SupportedBufferSize::Range { min, max } => Some((*min, *max)),
+                            SupportedBufferSize::Unknown => None,
+                        },
+                        sample_format: config.sample_format(),
+                    }).collect(),
+                    Err(_) => Vec::new(),
+                };
+                devices.push(DeviceInfo {
+                    is_default: default_device.as_ref() == Some(&name),
+                    name,
+                    configs,
+                });
+            }
+            hosts.push(HostInfo {
+                name: id.name().to_string(),
+                is_default: id == default_host,
+                devices,
+            });
+        }
+        hosts
+    }
+
+    pub fn get_selection(&self) -> &StreamSelection {
+        &self.selection
+    }
+
+    pub fn get_host(&self) -> &Host {
+        &self.host
+    }
+
+    fn stream_config(&self) -> StreamConfig {
+        StreamConfig {
+            channels: self.selection.channels,
+            sample_rate: SampleRate(self.selection.sample_rate),
+            buffer_size: match self.selection.buffer_size {
+                Some(frames) => BufferSize::Fixed(frames),
+                None => BufferSize::Default,
+            },
+        }
+    }
+
+    /// Longest buffer the device can ask for with the selected config, the requested size if there is one
+    fn max_buffer_frames(&self) -> usize {
+        if let Some(frames) = self.selection.buffer_size {
+            return frames as usize;
+        }
+        let Ok(configs) = self.device.supported_output_configs() else {
+            return CONVERSION_FRAMES;
+        };
+        configs
+            .filter(|config| config.channels() == self.selection.channels && config.sample_format() == self.selection.sample_format)
+            .filter_map(|config| match config.buffer_size() {
+                SupportedBufferSize::Range { max, .. } => Some(*max as usize),
+                SupportedBufferSize::Unknown => None,
+            })
+            .max()
+            .map_or(CONVERSION_FRAMES, |frames| frames.min(CONVERSION_FRAMES))
+    }
+
+    /// Pushes stream errors into the queue read by [`AudioBackend::collect_errors`]
+    fn error_callback(&self) -> impl FnMut(cpal::StreamError) + Send + 'static {
+        let errors = self.errors.clone();
+        move |err| {
+            errors.force_push(Error::Audio(AudioError::AudioStream(err.to_string())));
+        }
+    }
+
+    /// Renders into an `f32` buffer and converts it to the device's sample format.
+    /// The buffer is allocated before the stream starts, longer device buffers are rendered a part at a time
+    fn build_converting_stream<T: SizedSample + FromSample<f32>>(&self, mut callback: RenderCallback) -> Result<Stream> {
+        let channels = self.selection.channels as usize;
+        let mut buffer = vec![0.0; self.max_buffer_frames().max(1) * channels];
+        self.device.build_output_stream(
+            &self.stream_config(),
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                for data in data.chunks_mut(buffer.len()) {
+                    let buffer = &mut buffer[..data.len()];
+                    callback(buffer);
+                    for (sample, rendered) in data.iter_mut().zip(buffer.iter()) {
+                        *sample = T::from_sample(*rendered);
+                    }
+                }
+            },
+            self.error_callback(),
+            None
+        ).map_err(|err| Error::Audio(AudioError::AudioStreamBuild(err.to_string())))
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    fn sample_rate(&self) -> u32 {
+        self.selection.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.selection.channels as usize
+    }
+
+    fn start(&mut self, callback: RenderCallback) -> Result<()> {
+        let stream = match self.selection.sample_format {
+            SampleFormat::F32 => {
+                let mut callback = callback;
+                self.device.build_output_stream(
+                    &self.stream_config(),
+                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                        callback(data);
+                    },
+                    self.error_callback(),
+                    None // None=blocking, Some(Duration)=timeout
+                ).map_err(|err| Error::Audio(AudioError::AudioStreamBuild(err.to_string())))?
+            },
+            SampleFormat::I32 => self.build_converting_stream::<i32>(callback)?,
+            SampleFormat::I16 => self.build_converting_stream::<i16>(callback)?,
+            SampleFormat::U16 => self.build_converting_stream::<u16>(callback)?,
+            sample_format => return Err(Error::Audio(AudioError::AudioUnsupportedSampleFormat(sample_format))),
+        };
+
+        stream.play().map_err(|err| Error::Audio(AudioError::AudioStreamPlay(err.to_string())))?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        if let Some(stream) = self.stream.take() {
+            stream.pause().map_err(|err| Error::Audio(AudioError::AudioStreamPause(err.to_string())))?;
+        }
+        Ok(())
+    }
+
+    fn collect_errors(&mut self) -> Vec<Error> {
+        std::iter::from_fn(|| self.errors.pop()).collect()
+    }
+}
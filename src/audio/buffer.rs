@@ -0,0 +1,110 @@
+/// Reserved buffer slots, so adding nodes doesn't have to grow the pool
+const SLOT_CAPACITY: usize = 256;
+
+/// Preallocated output buffers for the nodes of an [`AudioGraph`](super::graph::AudioGraph).
+/// A buffer holds `block_size` samples for every channel of its node, one channel after the other.
+/// Every buffer is kept twice so feedback connections can read the block rendered before the current one
+pub struct BufferPool {
+    block_size: usize,
+    current: Vec<Vec<f32>>,
+    previous: Vec<Vec<f32>>,
+    free: Vec<usize>, //indices of released buffers, reused before allocating new ones
+}
+
+impl BufferPool {
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            current: Vec::with_capacity(SLOT_CAPACITY),
+            previous: Vec::with_capacity(SLOT_CAPACITY),
+            free: Vec::with_capacity(SLOT_CAPACITY),
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the index of a silent buffer with room for `channels` channels,
+    /// only allocates when no released buffer of that size is left
+    pub fn acquire(&mut self, channels: usize) -> usize {
+        if let Some(index) = self.take_free(channels * self.block_size) {
+            return index;
+        }
+        self.insert(NodeBuffers::new(channels, self.block_size)).0
+    }
+
+    /// Like [`BufferPool::acquire`] with buffers allocated by the caller.
+    /// Returns them again when a released buffer of the same size could be reused instead, so they can be dropped off the audio thread.
+    /// Once every slot is taken a released buffer of another size is swapped out and returned instead of growing the pool
+    pub fn insert(&mut self, buffers: NodeBuffers) -> (usize, Option<NodeBuffers>) {
+        if let Some(index) = self.take_free(buffers.current.len()) {
+            return (index, Some(buffers));
+        }
+        if self.current.len() == self.current.capacity() {
+            if let Some(index) = self.free.pop() {
+                let current = std::mem::replace(&mut self.current[index], buffers.current);
+                let previous = std::mem::replace(&mut self.previous[index], buffers.previous);
+                return (index, Some(NodeBuffers { current, previous }));
+            }
+        }
+        self.current.push(buffers.current);
+        self.previous.push(buffers.previous);
+        (self.current.len() - 1, None)
+    }
+
+    fn take_free(&mut self, length: usize) -> Option<usize> {
+        let free = self.free.iter().position(|index| self.current[*index].len() == length)?;
+        Some(self.free.swap_remove(free))
+    }
+
+    pub fn release(&mut self, index: usize) {
+        self.current[index].fill(0.0);
+        self.previous[index].fill(0.0);
+        self.free.push(index);
+    }
+
+    pub fn get(&self, index: usize) -> &[f32] {
+        &self.current[index]
+    }
+
+    /// The contents of the buffer before the last call to [`BufferPool::swap`]
+    pub fn get_previous(&self, index: usize) -> &[f32] {
+        &self.previous[index]
+    }
+
+    /// Moves a buffer out of the pool so it can be written while other buffers are read.
+    /// Has to be given back with [`BufferPool::restore`]
+    pub fn take(&mut self, index: usize) -> Vec<f32> {
+        std::mem::take(&mut self.current[index])
+    }
+
+    pub fn restore(&mut self, index: usize, buffer: Vec<f32>) {
+        self.current[index] = buffer;
+    }
+
+    /// Turns the current buffers into the previous ones, called once every block
+    pub fn swap(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.previous);
+    }
+}
+
+/// The buffers of a single node, allocated up front so they can be handed to a [`BufferPool`] on the audio thread
+pub struct NodeBuffers {
+    current: Vec<f32>,
+    previous: Vec<f32>,
+}
+
+impl NodeBuffers {
+    pub fn new(channels: usize, block_size: usize) -> Self {
+        Self {
+            current: vec![0.0; channels * block_size],
+            previous: vec![0.0; channels * block_size],
+        }
+    }
+
+    /// Samples in each of the two buffers
+    pub(crate) fn len(&self) -> usize {
+        self.current.len()
+    }
+}
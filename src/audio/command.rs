@@ -0,0 +1,405 @@
+use std::{fmt::Debug, sync::Arc};
+
+use crossbeam_queue::ArrayQueue;
+
+use crate::{Error, Result, error::AudioError};
+
+use super::{buffer::NodeBuffers, graph::{node_buffers, AudioDevice, AudioGraph, AudioNode, NodeId}};
+
+/// A change to a device that can't be expressed with a [`Parameter`](super::parameter::Parameter), like swapping its wavetable.
+/// Applied on the audio thread between two blocks, the edit is sent back afterwards so anything it swapped out is dropped on the controller's thread
+pub trait DeviceEdit: Send {
+    fn apply(&mut self, device: &mut dyn AudioDevice) -> Result<()>;
+}
+
+enum GraphCommand {
+    AddNode(NodeId, AudioNode, NodeBuffers),
+    RemoveNode(NodeId),
+    ReplaceDevice(NodeId, Box<dyn AudioDevice>, (u32, usize), NodeBuffers),
+    Connect(NodeId, NodeId),
+    ConnectFeedback(NodeId, NodeId),
+    Disconnect(NodeId, NodeId),
+    DisconnectFeedback(NodeId, NodeId),
+    ConnectModulation(NodeId, NodeId, &'static str),
+    ConnectSync(NodeId, NodeId, &'static str),
+    DisconnectModulation(NodeId, NodeId, &'static str),
+    Edit(NodeId, Box<dyn DeviceEdit>),
+}
+
+/// Sent back for every command, carrying whatever it freed so nothing gets deallocated on the audio thread
+struct Applied {
+    result: Result<()>,
+    node: Option<AudioNode>,
+    device: Option<Box<dyn AudioDevice>>,
+    buffers: Option<NodeBuffers>,
+    edit: Option<Box<dyn DeviceEdit>>,
+}
+
+impl Applied {
+    fn new(result: Result<()>) -> Self {
+        Self {
+            result,
+            node: None,
+            device: None,
+            buffers: None,
+            edit: None,
+        }
+    }
+}
+
+/// Splits `graph` into a [`GraphController`] for editing it and a [`GraphProcessor`] for rendering it on the audio thread.
+/// `capacity` is how many commands can wait to be applied at once
+pub fn command_queue(graph: AudioGraph, capacity: usize) -> (GraphController, GraphProcessor) {
+    let commands = Arc::new(ArrayQueue::new(capacity));
+    let applied = Arc::new(ArrayQueue::new(capacity));
+    let controller = GraphController {
+        commands: commands.clone(),
+        applied: applied.clone(),
+        pending: 0,
+        errors: Vec::new(),
+        current_id: graph.get_last_id(),
+        sample_rate: graph.get_sample_rate(),
+        max_block_size: graph.get_max_block_size(),
+    };
+    let processor = GraphProcessor {
+        graph,
+        commands,
+        applied,
+    };
+    (controller, processor)
+}
+
+/// Edits the graph of a [`GraphProcessor`] from another thread.
+/// Commands are applied before the next block is rendered, errors they caused are returned by [`GraphController::collect`]
+pub struct GraphController {
+    commands: Arc<ArrayQueue<GraphCommand>>,
+    applied: Arc<ArrayQueue<Applied>>,
+    pending: usize, //commands sent and not collected yet, never more than fit in the applied queue
+    errors: Vec<Error>,
+    current_id: NodeId,
+    sample_rate: u32, //of the graph when the queue was made, devices are prepared again on the audio thread if it changed since
+    max_block_size: usize,
+}
+
+impl GraphController {
+    fn send(&mut self, command: GraphCommand) -> Result<()> {
+        self.collect_applied();
+        if self.pending == self.commands.capacity() {
+            return Err(Error::Audio(AudioError::AudioCommandQueueFull));
+        }
+        if self.commands.push(command).is_err() {
+            return Err(Error::Audio(AudioError::AudioCommandQueueFull));
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Drops everything the audio thread sent back and keeps the errors
+    fn collect_applied(&mut self) {
+        while let Some(applied) = self.applied.pop() {
+            self.pending -= 1;
+            if let Err(err) = applied.result {
+                self.errors.push(err);
+            }
+        }
+    }
+
+    /// Returns the errors of every command applied since the last call
+    pub fn collect(&mut self) -> Vec<Error> {
+        self.collect_applied();
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Whether every command sent so far has been applied and collected
+    pub fn is_idle(&mut self) -> bool {
+        self.collect_applied();
+        self.pending == 0
+    }
+
+    /// Queues adding an unconnected node and returns the id it will have.
+    /// The node and its buffers are allocated here so the audio thread doesn't have to
+    pub fn add_node<D: AudioDevice + 'static>(&mut self, device: D) -> Result<NodeId> {
+        let buffers = node_buffers(&device, self.max_block_size);
+        let node = AudioNode::new(Box::new(device), self.sample_rate, self.max_block_size);
+        let id = self.current_id + 1;
+        self.send(GraphCommand::AddNode(id, node, buffers))?;
+        self.current_id = id;
+        Ok(id)
+    }
+
+    pub fn remove_node(&mut self, id: NodeId) -> Result<()> {
+        self.send(GraphCommand::RemoveNode(id))
+    }
+
+    /// Queues swapping the device of node `id`, keeping its connections.
+    /// Like [`GraphController::add_node`] the device is prepared and its buffers allocated here
+    pub fn replace_device<D: AudioDevice + 'static>(&mut self, id: NodeId, mut device: D) -> Result<()> {
+        device.prepare(self.sample_rate, self.max_block_size);
+        let buffers = node_buffers(&device, self.max_block_size);
+        let prepared = (self.sample_rate, self.max_block_size);
+        self.send(GraphCommand::ReplaceDevice(id, Box::new(device), prepared, buffers))
+    }
+
+    pub fn connect(&mut self, source: NodeId, destination: NodeId) -> Result<()> {
+        self.send(GraphCommand::Connect(source, destination))
+    }
+
+    pub fn connect_feedback(&mut self, source: NodeId, destination: NodeId) -> Result<()> {
+        self.send(GraphCommand::ConnectFeedback(source, destination))
+    }
+
+    pub fn disconnect(&mut self, source: NodeId, destination: NodeId) -> Result<()> {
+        self.send(GraphCommand::Disconnect(source, destination))
+    }
+
+    pub fn disconnect_feedback(&mut self, source: NodeId, destination: NodeId) -> Result<()> {
+        self.send(GraphCommand::DisconnectFeedback(source, destination))
+    }
+
+    /// Input names are static so the command can be sent without allocating
+    pub fn connect_modulation(&mut self, source: NodeId, destination: NodeId, input: &'static str) -> Result<()> {
+        self.send(GraphCommand::ConnectModulation(source, destination, input))
+    }
+
+    pub fn connect_sync(&mut self, source: NodeId, destination: NodeId, input: &'static str) -> Result<()> {
+        self.send(GraphCommand::ConnectSync(source, destination, input))
+    }
+
+    pub fn disconnect_modulation(&mut self, source: NodeId, destination: NodeId, input: &'static str) -> Result<()> {
+        self.send(GraphCommand::DisconnectModulation(source, destination, input))
+    }
+
+    /// Queues `edit` to be applied to the device of node `id`
+    pub fn edit<E: DeviceEdit + 'static>(&mut self, id: NodeId, edit: E) -> Result<()> {
+        self.send(GraphCommand::Edit(id, Box::new(edit)))
+    }
+}
+
+impl Debug for GraphController {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphController")
+            .field("pending", &self.pending)
+            .field("errors", &self.errors)
+            .field("current_id", &self.current_id)
+            .field("sample_rate", &self.sample_rate)
+            .field("max_block_size", &self.max_block_size)
+            .finish()
+    }
+}
+
+/// Renders a graph on the audio thread, applying the commands of its [`GraphController`] between blocks.
+/// Never blocks, allocates or frees: nodes are built by the controller, anything removed is sent back to it,
+/// and edits beyond [`NODE_CAPACITY`](super::graph::NODE_CAPACITY) nodes or [`CONNECTION_CAPACITY`](super::graph::CONNECTION_CAPACITY) connections are rejected
+pub struct GraphProcessor {
+    graph: AudioGraph,
+    commands: Arc<ArrayQueue<GraphCommand>>,
+    applied: Arc<ArrayQueue<Applied>>,
+}
+
+impl GraphProcessor {
+    /// Applies every queued command, then renders like [`AudioGraph::process_block`]
+    pub fn process_block(&mut self, output: &mut [f32], time: u64) {
+        while let Some(command) = self.commands.pop() {
+            let applied = self.apply(command);
+            // the controller never has more commands pending than fit into the queue
+            let _ = self.applied.push(applied);
+        }
+        self.graph.process_block(output, time);
+    }
+
+    fn apply(&mut self, command: GraphCommand) -> Applied {
+        let graph = &mut self.graph;
+        match command {
+            GraphCommand::AddNode(id, node, buffers) => {
+                if let Err(err) = graph.check_insertable(id) {
+                    return Applied { node: Some(node), buffers: Some(buffers), ..Applied::new(Err(err)) };
+                }
+                match graph.insert_node(id, node, buffers) {
+                    Ok(unused) => Applied { buffers: unused, ..Applied::new(Ok(())) },
+                    Err(err) => Applied::new(Err(err)),
+                }
+            },
+            GraphCommand::RemoveNode(id) => match graph.take_node(id) {
+                Ok(node) => Applied { node: Some(node), ..Applied::new(Ok(())) },
+                Err(err) => Applied::new(Err(err)),
+            },
+            GraphCommand::ReplaceDevice(id, device, prepared, buffers) => {
+                if let Err(err) = graph.check_editable(id) {
+                    return Applied { device: Some(device), buffers: Some(buffers), ..Applied::new(Err(err)) };
+                }
+                match graph.swap_device(id, device, prepared, buffers) {
+                    Ok((old, unused)) => Applied { device: Some(old), buffers: unused, ..Applied::new(Ok(())) },
+                    Err(err) => Applied::new(Err(err)),
+                }
+            },
+            GraphCommand::Connect(source, destination) => Applied::new(graph.connect(source, destination)),
+            GraphCommand::ConnectFeedback(source, destination) => Applied::new(graph.connect_feedback(source, destination)),
+            GraphCommand::Disconnect(source, destination) => Applied::new(graph.disconnect(source, destination)),
+            GraphCommand::DisconnectFeedback(source, destination) => Applied::new(graph.disconnect_feedback(source, destination)),
+            GraphCommand::ConnectModulation(source, destination, input) => Applied::new(graph.connect_modulation(source, destination, input)),
+            GraphCommand::ConnectSync(source, destination, input) => Applied::new(graph.connect_sync(source, destination, input)),
+            GraphCommand::DisconnectModulation(source, destination, input) => Applied::new(graph.disconnect_modulation(source, destination, input)),
+            GraphCommand::Edit(id, mut edit) => {
+                let result = graph.get_dyn_device_mut(id).and_then(|device| edit.apply(device));
+                Applied { edit: Some(edit), ..Applied::new(result) }
+            },
+        }
+    }
+
+    pub fn get_graph(&self) -> &AudioGraph {
+        &self.graph
+    }
+
+    /// Direct access to the graph, only meant for setting it up while it isn't playing
+    pub fn get_graph_mut(&mut self) -> &mut AudioGraph {
+        &mut self.graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audio::graph::{AudioDevice, AudioGraph, MASTER_NODE_ID, NODE_CAPACITY}, devices::amplifier::Amplifier, error::AudioError, Error, Result};
+    use super::{command_queue, DeviceEdit};
+
+    struct Constant(f32);
+
+    impl AudioDevice for Constant {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.0
+        }
+    }
+
+    struct SetConstant(f32);
+
+    impl DeviceEdit for SetConstant {
+        fn apply(&mut self, device: &mut dyn AudioDevice) -> Result<()> {
+            let device: &mut dyn std::any::Any = device;
+            let constant = device.downcast_mut::<Constant>().ok_or(Error::Audio(AudioError::AudioEditWrongDeviceType))?;
+            std::mem::swap(&mut constant.0, &mut self.0);
+            Ok(())
+        }
+    }
+
+    fn render(processor: &mut super::GraphProcessor) -> f32 {
+        let mut output = [0.0];
+        processor.process_block(&mut output, 0);
+        output[0]
+    }
+
+    #[test]
+    fn commands_apply_at_the_next_block() {
+        let (mut controller, mut processor) = command_queue(AudioGraph::with_max_block_size(48000, 8), 16);
+        let constant = controller.add_node(Constant(0.5)).unwrap();
+        controller.connect(constant, MASTER_NODE_ID).unwrap();
+        assert!(processor.get_graph().get_node(constant).is_err());
+        assert!(!controller.is_idle());
+
+        assert_eq!(render(&mut processor), 0.5);
+        assert!(controller.is_idle());
+
+        controller.edit(constant, SetConstant(0.25)).unwrap();
+        assert_eq!(render(&mut processor), 0.25);
+        controller.replace_device(constant, Amplifier::new(1.0)).unwrap();
+        assert_eq!(render(&mut processor), 0.0);
+        controller.remove_node(constant).unwrap();
+        render(&mut processor);
+        assert_eq!(processor.get_graph().get_node(MASTER_NODE_ID).unwrap().get_inputs(), []);
+        assert!(controller.collect().is_empty());
+    }
+
+    #[test]
+    fn ids_continue_after_the_graph() {
+        let mut graph = AudioGraph::new(48000);
+        let existing = graph.add_node(Amplifier::new(1.0));
+        let (mut controller, mut processor) = command_queue(graph, 4);
+        let added = controller.add_node(Amplifier::new(1.0)).unwrap();
+        assert!(added > existing);
+        render(&mut processor);
+        assert!(processor.get_graph().get_node(added).is_ok());
+        assert_eq!(processor.get_graph_mut().get_uid(), added + 1);
+    }
+
+    #[test]
+    fn errors_are_collected() {
+        let (mut controller, mut processor) = command_queue(AudioGraph::new(48000), 4);
+        controller.connect(42, MASTER_NODE_ID).unwrap();
+        controller.remove_node(MASTER_NODE_ID).unwrap();
+        controller.edit(MASTER_NODE_ID, SetConstant(1.0)).unwrap();
+        render(&mut processor);
+
+        let errors = controller.collect();
+        assert!(matches!(errors[..], [
+            Error::Audio(AudioError::AudioGraphInvalidId(42)),
+            Error::Audio(AudioError::AudioGraphCannotModifyMaster),
+            Error::Audio(AudioError::AudioEditWrongDeviceType),
+        ]));
+        assert!(controller.collect().is_empty());
+    }
+
+    #[test]
+    fn full_queue_is_an_error() {
+        let (mut controller, mut processor) = command_queue(AudioGraph::new(48000), 2);
+        let node = controller.add_node(Amplifier::new(1.0)).unwrap();
+        controller.connect(node, MASTER_NODE_ID).unwrap();
+        assert!(matches!(controller.disconnect(node, MASTER_NODE_ID), Err(Error::Audio(AudioError::AudioCommandQueueFull))));
+
+        render(&mut processor);
+        controller.disconnect(node, MASTER_NODE_ID).unwrap();
+    }
+
+    #[test]
+    fn nodes_beyond_capacity_are_rejected() {
+        let mut graph = AudioGraph::new(48000);
+        // the master output takes up one node
+        for _ in 1..NODE_CAPACITY - 1 {
+            graph.add_node(Amplifier::new(1.0));
+        }
+        let (mut controller, mut processor) = command_queue(graph, 4);
+        let last = controller.add_node(Constant(0.5)).unwrap();
+        let rejected = controller.add_node(Constant(0.5)).unwrap();
+        render(&mut processor);
+        assert!(processor.get_graph().get_node(last).is_ok());
+        assert!(processor.get_graph().get_node(rejected).is_err());
+        assert!(matches!(controller.collect()[..], [Error::Audio(AudioError::AudioGraphFull)]));
+
+        // removing a node makes room again
+        controller.remove_node(last).unwrap();
+        let added = controller.add_node(Constant(0.5)).unwrap();
+        controller.connect(added, MASTER_NODE_ID).unwrap();
+        assert_eq!(render(&mut processor), 0.5);
+        assert!(controller.collect().is_empty());
+    }
+
+    /// Records every call to [`AudioDevice::prepare`]
+    #[derive(Default)]
+    struct PrepareRecorder {
+        calls: Vec<(u32, usize)>,
+    }
+
+    impl AudioDevice for PrepareRecorder {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            0.0
+        }
+
+        fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+            self.calls.push((sample_rate, max_block_size));
+        }
+    }
+
+    #[test]
+    fn devices_are_prepared_by_the_controller() {
+        let (mut controller, mut processor) = command_queue(AudioGraph::with_max_block_size(48000, 1024), 4);
+        let node = controller.add_node(PrepareRecorder::default()).unwrap();
+        controller.replace_device(node, PrepareRecorder::default()).unwrap();
+        render(&mut processor);
+        assert!(controller.collect().is_empty());
+        // once in the controller, and not again on the audio thread
+        assert_eq!(processor.get_graph().get_device::<PrepareRecorder>(node).unwrap().calls, [(48000, 1024)]);
+
+        // unless the graph changed in between
+        processor.get_graph_mut().prepare(96000, 1024);
+        controller.replace_device(node, PrepareRecorder::default()).unwrap();
+        render(&mut processor);
+        assert_eq!(processor.get_graph().get_device::<PrepareRecorder>(node).unwrap().calls, [(48000, 1024), (96000, 1024)]);
+    }
+}
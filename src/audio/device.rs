@@ -0,0 +1,315 @@
+use cpal::SampleFormat;
+
+use crate::{Error, Result, error::AudioError};
+
+/// Sample rate picked when none is requested and the device supports it
+pub const PREFERRED_SAMPLE_RATE: u32 = 48000;
+
+/// Sample formats the backend can convert the rendered `f32` samples to, from most to least preferred
+pub const SUPPORTED_SAMPLE_FORMATS: [SampleFormat; 4] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+];
+
+/// An audio host like ALSA, JACK or WASAPI and its output devices
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+/// A range of stream configs an output device supports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// Smallest and largest buffer size in frames, `None` if the device doesn't tell
+    pub buffer_size: Option<(u32, u32)>,
+    pub sample_format: SampleFormat,
+}
+
+impl ConfigRange {
+    fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        (self.min_sample_rate..=self.max_sample_rate).contains(&sample_rate)
+    }
+
+    fn supports_buffer_size(&self, buffer_size: u32) -> bool {
+        match self.buffer_size {
+            Some((min, max)) => (min..=max).contains(&buffer_size),
+            None => true,
+        }
+    }
+
+    /// The requested rate if given, otherwise [`PREFERRED_SAMPLE_RATE`] or the highest rate if that isn't supported
+    fn pick_sample_rate(&self, requested: Option<u32>) -> u32 {
+        match requested {
+            Some(sample_rate) => sample_rate,
+            None if self.supports_sample_rate(PREFERRED_SAMPLE_RATE) => PREFERRED_SAMPLE_RATE,
+            None => self.max_sample_rate,
+        }
+    }
+}
+
+/// What to open, everything left at `None` is picked automatically
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamRequest {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+}
+
+impl StreamRequest {
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn with_device(mut self, device: impl Into<String>) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn with_buffer_size(mut self, buffer_size: u32) -> Self {
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+
+    pub fn with_sample_format(mut self, sample_format: SampleFormat) -> Self {
+        self.sample_format = Some(sample_format);
+        self
+    }
+}
+
+/// A fully specified output stream, picked from a [`StreamRequest`] by [`select_stream`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamSelection {
+    pub host: String,
+    pub device: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// `None` leaves the buffer size to the device
+    pub buffer_size: Option<u32>,
+    pub sample_format: SampleFormat,
+}
+
+fn find_host<'a>(hosts: &'a [HostInfo], name: Option<&str>) -> Result<&'a HostInfo> {
+    match name {
+        Some(name) => hosts.iter()
+            .find(|host| host.name.eq_ignore_ascii_case(name))
+            .ok_or(Error::Audio(AudioError::AudioHostNotFound(name.to_string()))),
+        None => hosts.iter()
+            .find(|host| host.is_default)
+            .or(hosts.first())
+            .ok_or(Error::Audio(AudioError::AudioNoHost)),
+    }
+}
+
+fn find_device<'a>(host: &'a HostInfo, name: Option<&str>) -> Result<&'a DeviceInfo> {
+    match name {
+        Some(name) => host.devices.iter()
+            .find(|device| device.name == name)
+            .ok_or(Error::Audio(AudioError::AudioDeviceNotFound(name.to_string()))),
+        None => host.devices.iter()
+            .find(|device| device.is_default)
+            .ok_or(Error::Audio(AudioError::AudioNoOutputDevice)),
+    }
+}
+
+/// Picks the host, device and config to open for `request` out of `hosts`.
+/// Unrequested settings prefer stereo, `f32` samples and [`PREFERRED_SAMPLE_RATE`].
+/// The error names the first requested setting no config of the device supports
+pub fn select_stream(hosts: &[HostInfo], request: &StreamRequest) -> Result<StreamSelection> {
+    let host = find_host(hosts, request.host.as_deref())?;
+    let device = find_device(host, request.device.as_deref())?;
+    if device.configs.is_empty() {
+        return Err(Error::Audio(AudioError::AudioNoSupportedConfig(device.name.clone())));
+    }
+
+    // narrow the configs down one requested setting at a time, so the error says which one failed
+    let mut configs: Vec<&ConfigRange> = device.configs.iter().collect();
+    if let Some(channels) = request.channels {
+        configs.retain(|config| config.channels == channels);
+        if configs.is_empty() {
+            return Err(Error::Audio(AudioError::AudioUnsupportedChannels(channels)));
+        }
+    }
+    match request.sample_format {
+        Some(sample_format) => {
+            configs.retain(|config| config.sample_format == sample_format);
+            if configs.is_empty() || !SUPPORTED_SAMPLE_FORMATS.contains(&sample_format) {
+                return Err(Error::Audio(AudioError::AudioUnsupportedSampleFormat(sample_format)));
+            }
+        },
+        None => {
+            configs.retain(|config| SUPPORTED_SAMPLE_FORMATS.contains(&config.sample_format));
+            if configs.is_empty() {
+                return Err(Error::Audio(AudioError::AudioNoSupportedConfig(device.name.clone())));
+            }
+        },
+    }
+    if let Some(sample_rate) = request.sample_rate {
+        configs.retain(|config| config.supports_sample_rate(sample_rate));
+        if configs.is_empty() {
+            return Err(Error::Audio(AudioError::AudioUnsupportedSampleRate(sample_rate)));
+        }
+    }
+    if let Some(buffer_size) = request.buffer_size {
+        configs.retain(|config| config.supports_buffer_size(buffer_size));
+        if configs.is_empty() {
+            return Err(Error::Audio(AudioError::AudioUnsupportedBufferSize(buffer_size)));
+        }
+    }
+
+    let format_rank = |config: &ConfigRange| SUPPORTED_SAMPLE_FORMATS.iter().position(|format| *format == config.sample_format);
+    let config = configs.into_iter()
+        .min_by_key(|config| (
+            config.channels != 2,
+            format_rank(config),
+            !config.supports_sample_rate(PREFERRED_SAMPLE_RATE),
+        ))
+        .expect("configs were checked to not be empty");
+
+    Ok(StreamSelection {
+        host: host.name.clone(),
+        device: device.name.clone(),
+        channels: config.channels,
+        sample_rate: config.pick_sample_rate(request.sample_rate),
+        buffer_size: request.buffer_size,
+        sample_format: config.sample_format,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use crate::{error::AudioError, Error};
+    use super::{select_stream, ConfigRange, DeviceInfo, HostInfo, StreamRequest};
+
+    fn config(channels: u16, sample_rates: (u32, u32), sample_format: SampleFormat) -> ConfigRange {
+        ConfigRange {
+            channels,
+            min_sample_rate: sample_rates.0,
+            max_sample_rate: sample_rates.1,
+            buffer_size: Some((64, 4096)),
+            sample_format,
+        }
+    }
+
+    fn mock_hosts() -> Vec<HostInfo> {
+        vec![
+            HostInfo {
+                name: "ALSA".to_string(),
+                is_default: true,
+                devices: vec![
+                    DeviceInfo {
+                        name: "hdmi".to_string(),
+                        is_default: false,
+                        configs: vec![config(8, (32000, 192000), SampleFormat::I32)],
+                    },
+                    DeviceInfo {
+                        name: "default".to_string(),
+                        is_default: true,
+                        configs: vec![
+                            config(1, (44100, 96000), SampleFormat::F32),
+                            config(2, (44100, 44100), SampleFormat::I16),
+                            config(2, (8000, 384000), SampleFormat::F32),
+                        ],
+                    },
+                    DeviceInfo {
+                        name: "broken".to_string(),
+                        is_default: false,
+                        configs: Vec::new(),
+                    },
+                ],
+            },
+            HostInfo {
+                name: "JACK".to_string(),
+                is_default: false,
+                devices: vec![DeviceInfo {
+                    name: "system".to_string(),
+                    is_default: true,
+                    configs: vec![config(2, (48000, 48000), SampleFormat::F32)],
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn defaults_prefer_stereo_float_at_48k() {
+        let selection = select_stream(&mock_hosts(), &StreamRequest::default()).unwrap();
+        assert_eq!((selection.host.as_str(), selection.device.as_str()), ("ALSA", "default"));
+        assert_eq!(selection.channels, 2);
+        assert_eq!(selection.sample_rate, 48000);
+        assert_eq!(selection.sample_format, SampleFormat::F32);
+        assert_eq!(selection.buffer_size, None);
+    }
+
+    #[test]
+    fn requested_settings_are_honored() {
+        let request = StreamRequest::default()
+            .with_sample_rate(44100)
+            .with_sample_format(SampleFormat::I16)
+            .with_buffer_size(256);
+        let selection = select_stream(&mock_hosts(), &request).unwrap();
+        assert_eq!((selection.channels, selection.sample_rate, selection.sample_format), (2, 44100, SampleFormat::I16));
+        assert_eq!(selection.buffer_size, Some(256));
+
+        let request = StreamRequest::default().with_host("jack");
+        assert_eq!(select_stream(&mock_hosts(), &request).unwrap().device, "system");
+
+        let request = StreamRequest::default().with_device("hdmi");
+        let selection = select_stream(&mock_hosts(), &request).unwrap();
+        assert_eq!((selection.channels, selection.sample_rate, selection.sample_format), (8, 48000, SampleFormat::I32));
+
+        let request = StreamRequest::default().with_channels(1).with_sample_rate(96000);
+        assert_eq!(select_stream(&mock_hosts(), &request).unwrap().channels, 1);
+    }
+
+    #[test]
+    fn unsupported_requests_are_typed_errors() {
+        let hosts = mock_hosts();
+        let select = |request: StreamRequest| select_stream(&hosts, &request).unwrap_err();
+        assert!(matches!(select(StreamRequest::default().with_host("ASIO")), Error::Audio(AudioError::AudioHostNotFound(host)) if host == "ASIO"));
+        assert!(matches!(select(StreamRequest::default().with_device("usb")), Error::Audio(AudioError::AudioDeviceNotFound(device)) if device == "usb"));
+        assert!(matches!(select(StreamRequest::default().with_device("broken")), Error::Audio(AudioError::AudioNoSupportedConfig(_))));
+        assert!(matches!(select(StreamRequest::default().with_channels(6)), Error::Audio(AudioError::AudioUnsupportedChannels(6))));
+        assert!(matches!(select(StreamRequest::default().with_sample_format(SampleFormat::U8)), Error::Audio(AudioError::AudioUnsupportedSampleFormat(SampleFormat::U8))));
+        assert!(matches!(select(StreamRequest::default().with_sample_rate(1000)), Error::Audio(AudioError::AudioUnsupportedSampleRate(1000))));
+        assert!(matches!(select(StreamRequest::default().with_buffer_size(16)), Error::Audio(AudioError::AudioUnsupportedBufferSize(16))));
+        // the sample rate exists, just not as 16 bit
+        let request = StreamRequest::default().with_sample_format(SampleFormat::I16).with_sample_rate(48000);
+        assert!(matches!(select(request), Error::Audio(AudioError::AudioUnsupportedSampleRate(48000))));
+    }
+
+    #[test]
+    fn missing_hosts_and_devices() {
+        assert!(matches!(select_stream(&[], &StreamRequest::default()), Err(Error::Audio(AudioError::AudioNoHost))));
+        let hosts = vec![HostInfo { name: "Null".to_string(), is_default: true, devices: Vec::new() }];
+        assert!(matches!(select_stream(&hosts, &StreamRequest::default()), Err(Error::Audio(AudioError::AudioNoOutputDevice))));
+    }
+}
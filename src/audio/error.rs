@@ -6,6 +6,46 @@ use strum_macros::AsRefStr;
 #[derive(Debug, Clone, AsRefStr)]
 pub enum AudioError {
     AudioGraphInvalidId(u32),
+    AudioGraphInvalidConnection(u32, u32),
+    AudioGraphAlreadyConnected(u32, u32),
+    AudioGraphNotConnected(u32, u32),
+    AudioGraphCycle(u32, u32),
+    AudioGraphCannotModifyMaster,
+    AudioGraphWrongDeviceType(u32),
+    AudioGraphDuplicateId(u32),
+    AudioGraphUnknownInput(u32),
+    AudioGraphNoSyncOutput(u32),
+    AudioGraphFull,
+    AudioGraphTooManyConnections(u32),
+    AudioGraphInvalidChannels(usize),
+    AudioCommandQueueFull,
+    AudioEditWrongDeviceType,
+    AudioNoHost,
+    AudioHostNotFound(String),
+    AudioHostUnavailable(String),
+    AudioNoOutputDevice,
+    AudioDeviceNotFound(String),
+    AudioDeviceQuery(String),
+    AudioNoSupportedConfig(String),
+    AudioUnsupportedChannels(u16),
+    AudioUnsupportedSampleRate(u32),
+    AudioUnsupportedBufferSize(u32),
+    AudioUnsupportedSampleFormat(cpal::SampleFormat),
+    AudioStreamBuild(String),
+    AudioStreamPlay(String),
+    AudioStreamPause(String),
+    AudioStream(String),
+    AudioThreadAlreadyRunning,
+    AudioThreadPoisoned,
+    AudioFileWrite(String),
+    AudioWavTooLong(u64),
+    AudioWavWrongLength(u64, u64),
+    AudioFileRead(String),
+    AudioWavNotWave,
+    AudioWavTruncated(String),
+    AudioWavMissingChunk(String),
+    AudioWavInvalidFormat,
+    AudioWavUnsupportedFormat(u16, u16),
 }
 
 
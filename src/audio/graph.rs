@@ -1,49 +1,547 @@
-use std::{borrow::BorrowMut, cell::RefCell, collections::HashMap, fmt::Debug, ops::DerefMut, panic::RefUnwindSafe, rc::Rc, sync::{Arc, Mutex}, time::Duration};
+use std::{any::Any, borrow::BorrowMut, cell::RefCell, collections::{HashMap, HashSet}, fmt::Debug, ops::DerefMut, panic::RefUnwindSafe, rc::Rc, sync::{Arc, Mutex}, time::Duration};
 use crate::{Error, Result, error::AudioError};
 use rodio::Source;
 
+use super::buffer::{BufferPool, NodeBuffers};
+
 //pub type AudioNodeBox = Box<AudioNode<dyn AudioDevice>>;
 
 
+pub type NodeId = u32;
+
+/// Id of the [`MasterOutput`] node every graph is created with
+pub const MASTER_NODE_ID: NodeId = 0;
+
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 512;
+
+/// Most channels a single node can have, channel counts reported by devices are clamped to this
+pub const MAX_CHANNELS: usize = 32;
+
+/// Nodes the graph reserves room for up front, nodes added through [`AudioGraph::insert_node`] beyond it are rejected
+pub const NODE_CAPACITY: usize = 256;
+
+/// Connections of each kind a node has room for, connecting more is rejected
+pub const CONNECTION_CAPACITY: usize = 32;
+
 pub struct AudioGraph {
-    nodes: HashMap<u32, AudioNode>,
-    master_node: AudioNode,
+    nodes: HashMap<NodeId, AudioNode>,
+    schedule: Vec<NodeId>, //every node feeding the master output, in the order they have to be rendered
+    visited: HashSet<NodeId>, //scratch space for walking the graph
+    stack: Vec<NodeId>,
+    pool: BufferPool, //output buffers for block processing
+    block_input: Vec<f32>, //inputs of a node mixed together, laid out like the buffers in the pool
     sample_rate: u32,
     //time: u64, //current time in samples
-    current_id: u32, //for getting a unique identifier to every node
+    current_id: NodeId, //for getting a unique identifier to every node
 }
 
 impl AudioGraph {
     pub fn new(sample_rate: u32) -> Self {
-        let mut master_node =
-            AudioNode::new(
-                Rc::new(Box::new(MasterOutput::new())),
-                Vec::new(),
-            );
-        master_node.set_id(0);
-        let mut nodes = HashMap::new();
-        nodes.insert(master_node.id.unwrap_or(0), master_node.clone());
-        Self {
-            nodes,
-            master_node,
+        Self::with_max_block_size(sample_rate, DEFAULT_MAX_BLOCK_SIZE)
+    }
+
+    /// `max_block_size` is the most frames a single device gets to process at once,
+    /// longer calls to [`AudioGraph::process_block`] are split up
+    pub fn with_max_block_size(sample_rate: u32, max_block_size: usize) -> Self {
+        let mut schedule = Vec::with_capacity(NODE_CAPACITY);
+        schedule.push(MASTER_NODE_ID);
+        let mut graph = Self {
+            nodes: HashMap::with_capacity(NODE_CAPACITY),
+            schedule,
+            visited: HashSet::with_capacity(NODE_CAPACITY),
+            stack: Vec::with_capacity(NODE_CAPACITY),
+            pool: BufferPool::new(max_block_size),
+            block_input: vec![0.0; MAX_CHANNELS * max_block_size],
             sample_rate,
             //time: 0,
-            current_id: 0,
+            current_id: MASTER_NODE_ID,
+        };
+        let mut master = AudioNode::new(Box::new(MasterOutput::new(1)), sample_rate, max_block_size);
+        graph.place_node(&mut master, None);
+        graph.nodes.insert(MASTER_NODE_ID, master);
+        graph
+    }
+
+    /// Fills `output` with interleaved frames of the master output starting at `time`,
+    /// rendering every scheduled device a block at a time.
+    /// Doesn't allocate, feedback connections are delayed by one block
+    pub fn process_block(&mut self, output: &mut [f32], time: u64) {
+        let block_size = self.pool.block_size();
+        let master = &self.nodes[&MASTER_NODE_ID];
+        let (master_buffer, channels) = (master.buffer, master.channels);
+        debug_assert_eq!(output.len() % channels, 0, "output has to hold whole frames");
+        let mut time = time;
+
+        for chunk in output.chunks_mut(block_size * channels) {
+            let length = chunk.len() / channels;
+            let context = ProcessContext {
+                sample_rate: self.sample_rate,
+                time,
+            };
+
+            for id in self.schedule.iter() {
+                let node = &self.nodes[id];
+                let (buffer, channels, input_channels) = (node.buffer, node.channels, node.input_channels);
+                let all_inputs = input_channels + node.modulation_inputs;
+
+                let input = &mut self.block_input[..all_inputs * block_size];
+                input.fill(0.0);
+                let (channel_input, modulation_input) = input.split_at_mut(input_channels * block_size);
+                for source in node.inputs.iter() {
+                    let source = &self.nodes[source];
+                    mix_channels(channel_input, node.input_channels, self.pool.get(source.buffer), source.channels, block_size, length);
+                }
+                for source in node.feedback_inputs.iter() {
+                    let source = &self.nodes[source];
+                    mix_channels(channel_input, node.input_channels, self.pool.get_previous(source.buffer), source.channels, block_size, length);
+                }
+                for modulation in node.modulations.iter() {
+                    let source = &self.nodes[&modulation.source];
+                    let input = &mut modulation_input[modulation.input * block_size..(modulation.input + 1) * block_size];
+                    if !modulation.sync {
+                        mix_channels(input, 1, self.pool.get(source.buffer), source.channels, block_size, length);
+                        continue;
+                    }
+                    // the later wrap wins, adding up fractions of a sample wouldn't mean anything
+                    let wraps = source.device.sync_output().unwrap_or(&[]);
+                    for (sample, wrap) in input[..length].iter_mut().zip(wraps) {
+                        *sample = sample.max(*wrap);
+                    }
+                }
+
+                let mut inputs: [&[f32]; MAX_CHANNELS] = [&[]; MAX_CHANNELS];
+                for (channel, samples) in input.chunks(block_size).enumerate() {
+                    inputs[channel] = &samples[..length];
+                }
+
+                let mut output = self.pool.take(buffer);
+                let mut outputs: [&mut [f32]; MAX_CHANNELS] = Default::default();
+                for (channel, samples) in output.chunks_mut(block_size).enumerate() {
+                    outputs[channel] = &mut samples[..length];
+                }
+                let device = &mut self.nodes.get_mut(id).expect("scheduled node missing").device;
+                device.process(&inputs[..all_inputs], &mut outputs[..channels], &context);
+                self.pool.restore(buffer, output);
+            }
+
+            let master = self.pool.get(master_buffer);
+            for (i, frame) in chunk.chunks_mut(channels).enumerate() {
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = master[channel * block_size + i];
+                }
+            }
+            self.pool.swap();
+            time += length as u64;
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Switches the graph to a new sample rate and maximum block size and calls [`AudioDevice::prepare`] on every device.
+    /// Reallocates every buffer if the block size changes, so it shouldn't be called while the graph is playing
+    pub fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        if max_block_size != self.pool.block_size() {
+            self.pool = BufferPool::new(max_block_size);
+            self.block_input = vec![0.0; MAX_CHANNELS * max_block_size];
+            for node in self.nodes.values_mut() {
+                node.buffer = self.pool.acquire(node.channels);
+            }
+        }
+        for node in self.nodes.values_mut() {
+            node.device.prepare(sample_rate, max_block_size);
+            node.prepared = (sample_rate, max_block_size);
+        }
+    }
+
+    /// Channel count of the frames written by [`AudioGraph::process_block`]
+    pub fn get_output_channels(&self) -> usize {
+        self.nodes[&MASTER_NODE_ID].channels
+    }
+
+    /// Changes the channel count of the master output, usually to the one reported by the output device.
+    /// Counts of 0 or above [`MAX_CHANNELS`] are rejected rather than clamped, the frames would no longer match the caller's
+    pub fn set_output_channels(&mut self, channels: usize) -> Result<()> {
+        if !(1..=MAX_CHANNELS).contains(&channels) {
+            return Err(Error::Audio(AudioError::AudioGraphInvalidChannels(channels)));
+        }
+        let amplitude = self.get_device::<MasterOutput>(MASTER_NODE_ID)
+            .map(|master| master.get_amplitude())
+            .unwrap_or(1.0);
+        let mut master = MasterOutput::new(channels);
+        master.set_amplitude(amplitude);
+        self.set_device(MASTER_NODE_ID, Box::new(master), None, None);
+        Ok(())
+    }
+
+    pub fn get_max_block_size(&self) -> usize {
+        self.pool.block_size()
+    }
+
+    /// Orders the nodes upstream of the master output so every node comes after all of its inputs.
+    /// Feedback inputs are read from the previous block, so they only need to be scheduled, not ordered.
+    /// Has to be called after every change to the connections, [`AudioGraph::connect`] guarantees there are no cycles
+    fn compile_schedule(&mut self) {
+        fn visit(nodes: &HashMap<NodeId, AudioNode>, id: NodeId, visited: &mut HashSet<NodeId>, schedule: &mut Vec<NodeId>) {
+            if !visited.insert(id) {
+                return;
+            }
+            let node = &nodes[&id];
+            for input in node.inputs.iter().chain(node.modulations.iter().map(|modulation| &modulation.source)) {
+                visit(nodes, *input, visited, schedule);
+            }
+            schedule.push(id);
+            for input in node.feedback_inputs.iter() {
+                visit(nodes, *input, visited, schedule);
+            }
+        }
+
+        self.visited.clear();
+        self.schedule.clear();
+        visit(&self.nodes, MASTER_NODE_ID, &mut self.visited, &mut self.schedule);
+    }
+
+    /// Checks whether `ancestor` feeds into `id`, directly or through other nodes, modulation inputs included. Feedback connections are ignored.
+    /// Every node is pushed at most once, so the stack never outgrows the node count
+    fn is_upstream(&mut self, ancestor: NodeId, id: NodeId) -> bool {
+        self.stack.clear();
+        self.visited.clear();
+        self.stack.push(id);
+        self.visited.insert(id);
+        while let Some(current) = self.stack.pop() {
+            if current == ancestor {
+                return true;
+            }
+            let node = &self.nodes[&current];
+            for input in node.inputs.iter().chain(node.modulations.iter().map(|modulation| &modulation.source)) {
+                if self.visited.insert(*input) {
+                    self.stack.push(*input);
+                }
+            }
+        }
+        false
+    }
+
+    /// Grows the scratch space for walking the graph to the node count, so edits on the audio thread never have to.
+    /// Only called when nodes are added off the audio thread, [`AudioGraph::insert_node`] stays below [`NODE_CAPACITY`]
+    fn reserve_scratch(&mut self) {
+        let nodes = self.nodes.len().max(NODE_CAPACITY);
+        self.schedule.reserve(nodes.saturating_sub(self.schedule.len()));
+        self.stack.reserve(nodes.saturating_sub(self.stack.len()));
+        self.visited.reserve(nodes.saturating_sub(self.visited.len()));
+    }
+
+    /// Ids of the nodes that get rendered, in the order they get rendered
+    pub fn get_schedule(&self) -> &[NodeId] {
+        &self.schedule
+    }
+
+    /// Takes a buffer for a node with `channels` channels out of the pool,
+    /// using `buffers` instead of allocating when given. Returns `buffers` again if they weren't needed
+    fn acquire_buffer(&mut self, channels: usize, buffers: Option<NodeBuffers>) -> (usize, Option<NodeBuffers>) {
+        match buffers {
+            Some(buffers) if buffers.len() == channels * self.pool.block_size() => self.pool.insert(buffers),
+            // allocated for a block size the graph was prepared away from
+            buffers => (self.pool.acquire(channels), buffers),
         }
     }
 
-    pub fn render(&mut self, time: u64) -> f32 {
-        0.0
+    /// Gives a node its output buffer, preparing its device again if the graph changed since the node was built
+    fn place_node(&mut self, node: &mut AudioNode, buffers: Option<NodeBuffers>) -> Option<NodeBuffers> {
+        if node.prepared != (self.sample_rate, self.pool.block_size()) {
+            node.prepared = (self.sample_rate, self.pool.block_size());
+            node.device.prepare(self.sample_rate, self.pool.block_size());
+        }
+        let (buffer, unused) = self.acquire_buffer(node.channels, buffers);
+        node.buffer = buffer;
+        unused
+    }
+
+    /// Swaps the device of a node, getting it a new buffer if the channel count changed.
+    /// `prepared` is the sample rate and block size `device` was already prepared for, if any, it's only prepared again if the graph's differ.
+    /// Returns the previous device and `buffers` if they weren't needed
+    fn set_device(&mut self, id: NodeId, mut device: Box<dyn AudioDevice>, prepared: Option<(u32, usize)>, buffers: Option<NodeBuffers>) -> (Box<dyn AudioDevice>, Option<NodeBuffers>) {
+        if prepared != Some((self.sample_rate, self.pool.block_size())) {
+            device.prepare(self.sample_rate, self.pool.block_size());
+        }
+        let channels = device.channels().clamp(1, MAX_CHANNELS);
+        let input_channels = device.input_channels().clamp(1, MAX_CHANNELS);
+        let modulation_inputs = device.modulation_inputs().len().min(MAX_CHANNELS - input_channels);
+
+        let old_channels = self.nodes[&id].channels;
+        let mut unused = buffers;
+        if old_channels != channels {
+            // released first, so a full pool can hand the slot to the new buffers
+            self.pool.release(self.nodes[&id].buffer);
+            let (buffer, left) = self.acquire_buffer(channels, unused);
+            unused = left;
+            self.nodes.get_mut(&id).expect("set_device called with an invalid id").buffer = buffer;
+        }
+
+        let node = self.nodes.get_mut(&id).expect("set_device called with an invalid id");
+        node.channels = channels;
+        node.input_channels = input_channels;
+        node.modulation_inputs = modulation_inputs;
+        node.prepared = (self.sample_rate, self.pool.block_size());
+        // modulation inputs the new device doesn't have are dropped
+        node.modulations.retain(|modulation| modulation.input < modulation_inputs);
+        (std::mem::replace(&mut node.device, device), unused)
+    }
+
+    /// Checks that `id` belongs to a node that can be removed or have its device replaced
+    pub fn check_editable(&self, id: NodeId) -> Result<()> {
+        if id == MASTER_NODE_ID {
+            return Err(Error::Audio(AudioError::AudioGraphCannotModifyMaster));
+        }
+        self.get_node(id).map(|_| ())
     }
 
-    pub fn get_node(&self, id: u32) -> Result<&AudioNode> {
+    /// Adds an unconnected node to the graph and returns its id, growing the graph past [`NODE_CAPACITY`] if needed
+    pub fn add_node<D: AudioDevice + 'static>(&mut self, device: D) -> NodeId {
+        let id = self.get_uid();
+        let mut node = AudioNode::new(Box::new(device), self.sample_rate, self.pool.block_size());
+        self.place_node(&mut node, None);
+        self.nodes.insert(id, node);
+        self.reserve_scratch();
+        id
+    }
+
+    /// Checks that a node with id `id` can be added with [`AudioGraph::insert_node`]
+    pub fn check_insertable(&self, id: NodeId) -> Result<()> {
+        if self.nodes.contains_key(&id) {
+            return Err(Error::Audio(AudioError::AudioGraphDuplicateId(id)));
+        }
+        if self.nodes.len() >= NODE_CAPACITY {
+            return Err(Error::Audio(AudioError::AudioGraphFull));
+        }
+        Ok(())
+    }
+
+    /// Adds a node built with [`AudioNode::new`] under an id handed out elsewhere, using `buffers` from [`node_buffers`] instead of allocating.
+    /// Returns the buffers again if a released buffer could be reused. Rejects nodes beyond [`NODE_CAPACITY`] so it never allocates
+    pub fn insert_node(&mut self, id: NodeId, mut node: AudioNode, buffers: NodeBuffers) -> Result<Option<NodeBuffers>> {
+        self.check_insertable(id)?;
+        self.current_id = self.current_id.max(id);
+        let unused = self.place_node(&mut node, Some(buffers));
+        self.nodes.insert(id, node);
+        Ok(unused)
+    }
+
+    /// Removes a node and every connection to or from it, returning its device
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Box<dyn AudioDevice>> {
+        Ok(self.take_node(id)?.device)
+    }
+
+    /// Like [`AudioGraph::remove_node`], returning the whole node so its connections can be freed elsewhere
+    pub fn take_node(&mut self, id: NodeId) -> Result<AudioNode> {
+        self.check_editable(id)?;
+        let node = self.nodes.remove(&id).ok_or(Error::Audio(AudioError::AudioGraphInvalidId(id)))?;
+        self.pool.release(node.buffer);
+        for node in self.nodes.values_mut() {
+            node.inputs.retain(|input| *input != id);
+            node.feedback_inputs.retain(|input| *input != id);
+            node.modulations.retain(|modulation| modulation.source != id);
+        }
+        self.compile_schedule();
+        Ok(node)
+    }
+
+    /// Feeds the output of `from` into the input of `to`, connections that would form a cycle are rejected
+    pub fn connect(&mut self, from: NodeId, to: NodeId) -> Result<()> {
+        self.get_node(from)?;
+        if from == MASTER_NODE_ID {
+            return Err(Error::Audio(AudioError::AudioGraphInvalidConnection(from, to)));
+        }
+        if self.get_node(to)?.is_connected(from) {
+            return Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(from, to)));
+        }
+        if self.get_node(to)?.inputs.len() >= CONNECTION_CAPACITY {
+            return Err(Error::Audio(AudioError::AudioGraphTooManyConnections(to)));
+        }
+        if self.is_upstream(to, from) {
+            return Err(Error::Audio(AudioError::AudioGraphCycle(from, to)));
+        }
+        self.get_node_mut(to)?.inputs.push(from);
+        self.compile_schedule();
+        Ok(())
+    }
+
+    /// Feeds the output `from` rendered in the previous block into the input of `to`.
+    /// Unlike [`AudioGraph::connect`] this can be used to build cycles on purpose, including a node feeding itself
+    pub fn connect_feedback(&mut self, from: NodeId, to: NodeId) -> Result<()> {
+        self.get_node(from)?;
+        if from == MASTER_NODE_ID {
+            return Err(Error::Audio(AudioError::AudioGraphInvalidConnection(from, to)));
+        }
+        let node = self.get_node_mut(to)?;
+        if node.is_connected(from) {
+            return Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(from, to)));
+        }
+        if node.feedback_inputs.len() >= CONNECTION_CAPACITY {
+            return Err(Error::Audio(AudioError::AudioGraphTooManyConnections(to)));
+        }
+        node.feedback_inputs.push(from);
+        self.compile_schedule();
+        Ok(())
+    }
+
+    /// Feeds the output of `from` mixed down to mono into the modulation input of `to` called `input`,
+    /// see [`AudioDevice::modulation_inputs`]. Like [`AudioGraph::connect`] this rejects cycles
+    pub fn connect_modulation(&mut self, from: NodeId, to: NodeId, input: &str) -> Result<()> {
+        self.add_modulation(from, to, input, false)
+    }
+
+    /// Feeds where `from` wraps around into the modulation input of `to` called `input` instead of its output,
+    /// see [`AudioDevice::sync_output`]. Only nodes with a sync output can be connected
+    pub fn connect_sync(&mut self, from: NodeId, to: NodeId, input: &str) -> Result<()> {
+        if self.get_node(from)?.device.sync_output().is_none() {
+            return Err(Error::Audio(AudioError::AudioGraphNoSyncOutput(from)));
+        }
+        self.add_modulation(from, to, input, true)
+    }
+
+    fn add_modulation(&mut self, from: NodeId, to: NodeId, input: &str, sync: bool) -> Result<()> {
+        self.get_node(from)?;
+        if from == MASTER_NODE_ID {
+            return Err(Error::Audio(AudioError::AudioGraphInvalidConnection(from, to)));
+        }
+        let node = self.get_node(to)?;
+        let input = node.modulation_input(input).ok_or(Error::Audio(AudioError::AudioGraphUnknownInput(to)))?;
+        if node.modulations.iter().any(|modulation| modulation.source == from && modulation.input == input) {
+            return Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(from, to)));
+        }
+        if node.modulations.len() >= CONNECTION_CAPACITY {
+            return Err(Error::Audio(AudioError::AudioGraphTooManyConnections(to)));
+        }
+        if self.is_upstream(to, from) {
+            return Err(Error::Audio(AudioError::AudioGraphCycle(from, to)));
+        }
+        self.get_node_mut(to)?.modulations.push(Modulation { source: from, input, sync });
+        self.compile_schedule();
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, from: NodeId, to: NodeId) -> Result<()> {
+        self.get_node(from)?;
+        let node = self.get_node_mut(to)?;
+        let index = node.inputs.iter()
+            .position(|input| *input == from)
+            .ok_or(Error::Audio(AudioError::AudioGraphNotConnected(from, to)))?;
+        node.inputs.remove(index);
+        self.compile_schedule();
+        Ok(())
+    }
+
+    pub fn disconnect_feedback(&mut self, from: NodeId, to: NodeId) -> Result<()> {
+        self.get_node(from)?;
+        let node = self.get_node_mut(to)?;
+        let index = node.feedback_inputs.iter()
+            .position(|input| *input == from)
+            .ok_or(Error::Audio(AudioError::AudioGraphNotConnected(from, to)))?;
+        node.feedback_inputs.remove(index);
+        self.compile_schedule();
+        Ok(())
+    }
+
+    /// Removes a connection made with either [`AudioGraph::connect_modulation`] or [`AudioGraph::connect_sync`]
+    pub fn disconnect_modulation(&mut self, from: NodeId, to: NodeId, input: &str) -> Result<()> {
+        self.get_node(from)?;
+        let node = self.get_node_mut(to)?;
+        let input = node.modulation_input(input).ok_or(Error::Audio(AudioError::AudioGraphUnknownInput(to)))?;
+        let index = node.modulations.iter()
+            .position(|modulation| modulation.source == from && modulation.input == input)
+            .ok_or(Error::Audio(AudioError::AudioGraphNotConnected(from, to)))?;
+        node.modulations.remove(index);
+        self.compile_schedule();
+        Ok(())
+    }
+
+    /// Swaps the device of a node while keeping all of its connections, returning the previous device
+    pub fn replace_device<D: AudioDevice + 'static>(&mut self, id: NodeId, device: D) -> Result<Box<dyn AudioDevice>> {
+        self.check_editable(id)?;
+        Ok(self.set_device(id, Box::new(device), None, None).0)
+    }
+
+    /// Like [`AudioGraph::replace_device`] with a device already prepared for the sample rate and block size in `prepared`,
+    /// using `buffers` from [`node_buffers`] instead of allocating if the channel count changes.
+    /// The device is only prepared again if the graph changed since, returns the previous device and the buffers if they weren't needed
+    pub fn swap_device(&mut self, id: NodeId, device: Box<dyn AudioDevice>, prepared: (u32, usize), buffers: NodeBuffers) -> Result<(Box<dyn AudioDevice>, Option<NodeBuffers>)> {
+        self.check_editable(id)?;
+        Ok(self.set_device(id, device, Some(prepared), Some(buffers)))
+    }
+
+    /// Borrows the device of a node as its concrete type
+    pub fn get_device<D: AudioDevice>(&self, id: NodeId) -> Result<&D> {
+        let device: &dyn Any = self.get_node(id)?.device.as_ref();
+        device.downcast_ref().ok_or(Error::Audio(AudioError::AudioGraphWrongDeviceType(id)))
+    }
+
+    /// Mutably borrows the device of a node as its concrete type, to change its parameters
+    pub fn get_device_mut<D: AudioDevice>(&mut self, id: NodeId) -> Result<&mut D> {
+        let device: &mut dyn Any = self.get_node_mut(id)?.device.as_mut();
+        device.downcast_mut().ok_or(Error::Audio(AudioError::AudioGraphWrongDeviceType(id)))
+    }
+
+    pub fn get_dyn_device_mut(&mut self, id: NodeId) -> Result<&mut dyn AudioDevice> {
+        Ok(self.get_node_mut(id)?.device.as_mut())
+    }
+
+    pub fn get_node(&self, id: NodeId) -> Result<&AudioNode> {
         self.nodes.get(&id).ok_or(Error::Audio(AudioError::AudioGraphInvalidId(id)))
     }
 
-    pub fn get_uid(&mut self) -> u32 {
+    fn get_node_mut(&mut self, id: NodeId) -> Result<&mut AudioNode> {
+        self.nodes.get_mut(&id).ok_or(Error::Audio(AudioError::AudioGraphInvalidId(id)))
+    }
+
+    pub fn get_uid(&mut self) -> NodeId {
         self.current_id += 1;
         self.current_id
     }
+
+    /// The id of the node added last, ids handed out outside of the graph have to be above it
+    pub fn get_last_id(&self) -> NodeId {
+        self.current_id
+    }
+}
+
+/// Allocates the buffers a node for `device` needs, for adding it to a graph with [`AudioGraph::insert_node`] without allocating there
+pub fn node_buffers(device: &dyn AudioDevice, max_block_size: usize) -> NodeBuffers {
+    NodeBuffers::new(device.channels().clamp(1, MAX_CHANNELS), max_block_size)
+}
+
+/// Adds the first `length` frames of `source` onto `destination`, both laid out like the buffers in a [`BufferPool`].
+/// Mono sources are copied to every channel, sources with more channels than the destination are folded down onto it
+fn mix_channels(destination: &mut [f32], destination_channels: usize, source: &[f32], source_channels: usize, block_size: usize, length: usize) {
+    if source_channels == 1 {
+        let source = &source[..length];
+        for destination in destination.chunks_mut(block_size) {
+            destination.iter_mut().zip(source).for_each(|(sample, source)| *sample += source);
+        }
+        return;
+    }
+
+    let gain = if source_channels > destination_channels {
+        destination_channels as f32 / source_channels as f32
+    } else {
+        1.0
+    };
+    for (channel, source) in source.chunks(block_size).enumerate() {
+        let offset = (channel % destination_channels) * block_size;
+        let destination = &mut destination[offset..offset + length];
+        destination.iter_mut().zip(source).for_each(|(sample, source)| *sample += source * gain);
+    }
+}
+
+impl Debug for AudioGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AudioGraph")
+            .field("sample_rate", &self.sample_rate)
+            .field("schedule", &self.schedule)
+            .finish_non_exhaustive()
+    }
 }
 
 /*
@@ -61,7 +559,7 @@ impl Source for AudioGraph {
     fn channels(&self) -> u16 {
         return 1;
     }
-    
+
     fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
@@ -76,46 +574,145 @@ impl Source for AudioGraph {
 }
 */
 
-#[derive(Clone)]
 pub struct AudioNode {
-    device: Rc<Box<dyn AudioDevice>>, //dyn AudioDevice
-    children: Vec<AudioNode>,
-    id: Option<u32>,
+    device: Box<dyn AudioDevice>,
+    inputs: Vec<NodeId>,
+    feedback_inputs: Vec<NodeId>,
+    modulations: Vec<Modulation>,
+    buffer: usize, //index of the output buffer in the graph's pool
+    channels: usize,
+    input_channels: usize,
+    modulation_inputs: usize,
+    prepared: (u32, usize), //sample rate and maximum block size the device was last prepared for
 }
 
 impl AudioNode {
-    pub fn new(device: Rc<Box<dyn AudioDevice>>, children: Vec<AudioNode>) -> Self {
+    /// An unconnected node for `device`, prepared and with room for [`CONNECTION_CAPACITY`] connections of each kind.
+    /// Built off the audio thread and added with [`AudioGraph::insert_node`], so nothing has to be allocated there
+    pub fn new(mut device: Box<dyn AudioDevice>, sample_rate: u32, max_block_size: usize) -> Self {
+        device.prepare(sample_rate, max_block_size);
+        let channels = device.channels().clamp(1, MAX_CHANNELS);
+        let input_channels = device.input_channels().clamp(1, MAX_CHANNELS);
+        let modulation_inputs = device.modulation_inputs().len().min(MAX_CHANNELS - input_channels);
         Self {
             device,
-            children,
-            id: None,
+            inputs: Vec::with_capacity(CONNECTION_CAPACITY),
+            feedback_inputs: Vec::with_capacity(CONNECTION_CAPACITY),
+            modulations: Vec::with_capacity(CONNECTION_CAPACITY),
+            buffer: 0, //given out by the graph the node is added to
+            channels,
+            input_channels,
+            modulation_inputs,
+            prepared: (sample_rate, max_block_size),
         }
     }
 
-    fn set_id(&mut self, id: u32) {
-        self.id = Some(id);
+    /// Index of the modulation input called `name`
+    fn modulation_input(&self, name: &str) -> Option<usize> {
+        self.device.modulation_inputs().iter()
+            .take(self.modulation_inputs)
+            .position(|input| *input == name)
+    }
+
+    fn is_connected(&self, input: NodeId) -> bool {
+        self.inputs.contains(&input) || self.feedback_inputs.contains(&input)
+    }
+
+    /// Ids of the nodes feeding into this node
+    pub fn get_inputs(&self) -> &[NodeId] {
+        &self.inputs
+    }
+
+    /// Ids of the nodes feeding their previous output into this node
+    pub fn get_feedback_inputs(&self) -> &[NodeId] {
+        &self.feedback_inputs
+    }
+
+    /// Connections into the modulation inputs of this node
+    pub fn get_modulations(&self) -> &[Modulation] {
+        &self.modulations
     }
 
-    pub fn render(&self, time: u64) -> f32 {
-        self.device.render(&self.children, time)
+    pub fn get_channels(&self) -> usize {
+        self.channels
     }
 }
 
-pub trait AudioDevice {
-    fn render(&self, children: &Vec<AudioNode>, time: u64) -> f32;
+/// A connection into one of the modulation inputs of a node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Modulation {
+    pub source: NodeId,
+    pub input: usize, //index in the device's modulation inputs
+    pub sync: bool, //whether the sync output of the source is fed in instead of its output
+}
+
+/// Information about the block passed to [`AudioDevice::process`]
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessContext {
+    pub sample_rate: u32,
+    pub time: u64, //time of the first sample in the block, in samples
+}
+
+/// Devices are owned by the [`AudioGraph`] and rendered on the audio thread, so they can keep state between samples
+pub trait AudioDevice: Any + Send {
+    /// Renders the next sample of a mono device,
+    /// `inputs` holds the current sample of every input channel with all connections mixed together, followed by every modulation input
+    fn render(&mut self, inputs: &[f32], time: u64) -> f32;
+
+    /// Renders a whole block at once. `inputs` holds a buffer for every input channel with all connections mixed together,
+    /// followed by one for every modulation input. `outputs` a buffer for every output channel to fill, all as long as the block.
+    /// The default implementation calls [`AudioDevice::render`] for every sample, devices with more than one channel have to override it
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], context: &ProcessContext) {
+        let mut frame = [0.0; MAX_CHANNELS];
+        for (i, sample) in outputs[0].iter_mut().enumerate() {
+            for (channel, input) in inputs.iter().enumerate() {
+                frame[channel] = input[i];
+            }
+            *sample = self.render(&frame[..inputs.len()], context.time + i as u64);
+        }
+    }
+
+    /// Called before the device renders its first block, and again whenever the graph's sample rate or maximum block size changes.
+    /// Anything derived from them, like filter coefficients or phase increments, should be recomputed here
+    fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+    /// Number of output channels
+    fn channels(&self) -> usize {
+        1
+    }
+
+    /// Number of input channels, mono inputs are copied to every channel
+    fn input_channels(&self) -> usize {
+        self.channels()
+    }
+
+    /// Names of the mono inputs that only get the connections made with [`AudioGraph::connect_modulation`].
+    /// They are passed after the input channels, in this order
+    fn modulation_inputs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// For every sample of the block processed last, 0 if the device didn't wrap around during it,
+    /// otherwise how far into the sample it wrapped in (0, 1]. Devices without cycles have none.
+    /// Read by the graph for connections made with [`AudioGraph::connect_sync`]
+    fn sync_output(&self) -> Option<&[f32]> {
+        None
+    }
 }
 
 pub struct MasterOutput {
     amplitude: f32,
+    channels: usize,
 }
 
 impl MasterOutput {
-    pub fn new() -> Self {
+    pub fn new(channels: usize) -> Self {
         Self {
-            amplitude: 1.0
+            amplitude: 1.0,
+            channels,
         }
     }
-    
+
     pub fn set_amplitude(&mut self, amplitude: f32) {
         self.amplitude = amplitude;
     }
@@ -126,17 +723,25 @@ impl MasterOutput {
 }
 
 impl AudioDevice for MasterOutput {
-    fn render(&self, children: &Vec<AudioNode>, time: u64) -> f32 {
-        render_nodes(children, time) * self.amplitude
+    fn render(&mut self, inputs: &[f32], _time: u64) -> f32 {
+        mix_inputs(inputs) * self.amplitude
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], _context: &ProcessContext) {
+        for (output, input) in outputs.iter_mut().zip(inputs) {
+            for (sample, input) in output.iter_mut().zip(input.iter()) {
+                *sample = input * self.amplitude;
+            }
+        }
     }
-}
 
-pub fn render_nodes(audio_nodes: &Vec<AudioNode>, time: u64) -> f32 {
-    let mut sample = 0.0;
-    for node in audio_nodes {
-        sample += node.render(time);
+    fn channels(&self) -> usize {
+        self.channels
     }
-    sample
+}
+
+pub fn mix_inputs(inputs: &[f32]) -> f32 {
+    inputs.iter().sum()
 }
 
 /*
@@ -145,4 +750,514 @@ pub enum NodeType {
     Oscillator,
     Amplifier,
 }
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use crate::{devices::{amplifier::Amplifier, oscillator::WaveTableOscillator}, error::AudioError, synthesis::{waveforms::WaveForm, wavetable::WaveTable}, Error};
+    use super::{AudioDevice, AudioGraph, MasterOutput, Modulation, NodeId, ProcessContext, CONNECTION_CAPACITY, MASTER_NODE_ID};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// 375hz at 48khz is exactly one 128 sample table per period, so every sample lands on a table entry
+    fn sine_oscillator(frequency: f32) -> WaveTableOscillator {
+        let wavetable = WaveTable::from_waveform(WaveForm::Sine, 128).unwrap();
+        let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, wavetable);
+        oscillator.set_frequency(frequency);
+        oscillator.activate();
+        oscillator
+    }
+
+    /// Outputs 1.0 and counts how often it has been rendered
+    #[derive(Default)]
+    struct CountingDevice {
+        renders: u32,
+    }
+
+    impl AudioDevice for CountingDevice {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.renders += 1;
+            1.0
+        }
+    }
+
+    /// Outputs a constant value on every channel
+    struct ConstantDevice {
+        values: Vec<f32>,
+    }
+
+    impl AudioDevice for ConstantDevice {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.values[0]
+        }
+
+        fn process(&mut self, _inputs: &[&[f32]], outputs: &mut [&mut [f32]], _context: &ProcessContext) {
+            for (output, value) in outputs.iter_mut().zip(self.values.iter()) {
+                output.fill(*value);
+            }
+        }
+
+        fn channels(&self) -> usize {
+            self.values.len()
+        }
+    }
+
+    /// Adds its input to its modulation inputs weighted by 10 and 100, so each of them shows up in its own digit
+    struct ModulatedDevice;
+
+    impl AudioDevice for ModulatedDevice {
+        fn render(&mut self, inputs: &[f32], _time: u64) -> f32 {
+            inputs[0] + 10.0 * inputs[1] + 100.0 * inputs[2]
+        }
+
+        fn modulation_inputs(&self) -> &'static [&'static str] {
+            &["tens", "hundreds"]
+        }
+    }
+
+    fn expected_sine(time: u64) -> f32 {
+        (TAU * (time % 128) as f32 / 128.0).sin()
+    }
+
+    /// Renders `length` frames starting at `time`
+    fn render(graph: &mut AudioGraph, time: u64, length: usize) -> Vec<f32> {
+        let mut buffer = vec![0.0; length * graph.get_output_channels()];
+        graph.process_block(&mut buffer, time);
+        buffer
+    }
+
+    #[test]
+    fn empty_graph_is_silent() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        assert!(render(&mut graph, 0, 64).iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn renders_oscillator_amplifier_chain() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        let amplifier = graph.add_node(Amplifier::new(0.5));
+        graph.connect(oscillator, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        for (time, sample) in render(&mut graph, 0, 512).into_iter().enumerate() {
+            let expected = 0.5 * expected_sine(time as u64);
+            assert!((sample - expected).abs() < 1e-3, "sample {time}: expected {expected}, got {sample}");
+        }
+    }
+
+    #[test]
+    fn mixes_every_connected_node() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let amplifier = graph.add_node(Amplifier::new(0.25));
+        for _ in 0..2 {
+            let oscillator = graph.add_node(sine_oscillator(375.0));
+            graph.connect(oscillator, amplifier).unwrap();
+        }
+        let direct = graph.add_node(sine_oscillator(375.0));
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        graph.connect(direct, MASTER_NODE_ID).unwrap();
+
+        for (time, sample) in render(&mut graph, 0, 256).into_iter().enumerate() {
+            let expected = 1.5 * expected_sine(time as u64);
+            assert!((sample - expected).abs() < 1e-3, "sample {time}: expected {expected}, got {sample}");
+        }
+    }
+
+    #[test]
+    fn unconnected_nodes_are_not_rendered() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        graph.add_node(sine_oscillator(375.0));
+        assert_eq!(render(&mut graph, 32, 1), [0.0]);
+    }
+
+    #[test]
+    fn disconnect_and_remove() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        let amplifier = graph.add_node(Amplifier::new(1.0));
+        graph.connect(oscillator, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        assert!(render(&mut graph, 0, 33)[32] > 0.5);
+
+        graph.disconnect(oscillator, amplifier).unwrap();
+        assert_eq!(render(&mut graph, 32, 1), [0.0]);
+
+        graph.connect(oscillator, amplifier).unwrap();
+        graph.remove_node(amplifier).unwrap();
+        assert!(graph.get_node(MASTER_NODE_ID).unwrap().get_inputs().is_empty());
+        assert_eq!(render(&mut graph, 32, 1), [0.0]);
+    }
+
+    #[test]
+    fn replace_device_keeps_connections() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        let amplifier = graph.add_node(Amplifier::new(1.0));
+        graph.connect(oscillator, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        graph.replace_device(amplifier, Amplifier::new(0.0)).unwrap();
+        assert_eq!(render(&mut graph, 32, 1), [0.0]);
+        assert_eq!(graph.get_node(amplifier).unwrap().get_inputs(), &[oscillator]);
+    }
+
+    #[test]
+    fn rejects_invalid_edits() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+
+        assert!(matches!(graph.connect(42, MASTER_NODE_ID), Err(Error::Audio(AudioError::AudioGraphInvalidId(42)))));
+        assert!(matches!(graph.connect(oscillator, 42), Err(Error::Audio(AudioError::AudioGraphInvalidId(42)))));
+        assert!(matches!(graph.connect(MASTER_NODE_ID, oscillator), Err(Error::Audio(AudioError::AudioGraphInvalidConnection(..)))));
+        assert!(matches!(graph.connect(oscillator, oscillator), Err(Error::Audio(AudioError::AudioGraphCycle(..)))));
+        assert!(matches!(graph.disconnect(oscillator, MASTER_NODE_ID), Err(Error::Audio(AudioError::AudioGraphNotConnected(..)))));
+
+        graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+        assert!(matches!(graph.connect(oscillator, MASTER_NODE_ID), Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(..)))));
+
+        assert!(matches!(graph.remove_node(MASTER_NODE_ID), Err(Error::Audio(AudioError::AudioGraphCannotModifyMaster))));
+        assert!(matches!(graph.replace_device(MASTER_NODE_ID, Amplifier::new(1.0)), Err(Error::Audio(AudioError::AudioGraphCannotModifyMaster))));
+        assert!(matches!(graph.remove_node(42), Err(Error::Audio(AudioError::AudioGraphInvalidId(42)))));
+        assert!(matches!(graph.replace_device(42, Amplifier::new(1.0)), Err(Error::Audio(AudioError::AudioGraphInvalidId(42)))));
+    }
+
+    #[test]
+    fn shared_nodes_render_once() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let lfo = graph.add_node(CountingDevice::default());
+        for _ in 0..3 {
+            let amplifier = graph.add_node(Amplifier::new(1.0));
+            graph.connect(lfo, amplifier).unwrap();
+            graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        }
+
+        assert_eq!(render(&mut graph, 0, 1), [3.0]);
+        assert_eq!(graph.get_device::<CountingDevice>(lfo).unwrap().renders, 1);
+        render(&mut graph, 1, 1);
+        assert_eq!(graph.get_device::<CountingDevice>(lfo).unwrap().renders, 2);
+    }
+
+    #[test]
+    fn schedule_renders_inputs_first() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        let first = graph.add_node(Amplifier::new(1.0));
+        let second = graph.add_node(Amplifier::new(1.0));
+        graph.connect(second, MASTER_NODE_ID).unwrap();
+        graph.connect(first, second).unwrap();
+        graph.connect(oscillator, first).unwrap();
+        graph.connect(oscillator, second).unwrap();
+
+        assert_eq!(graph.get_schedule(), &[oscillator, first, second, MASTER_NODE_ID]);
+
+        graph.disconnect(first, second).unwrap();
+        assert_eq!(graph.get_schedule(), &[oscillator, second, MASTER_NODE_ID]);
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let a = graph.add_node(Amplifier::new(1.0));
+        let b = graph.add_node(Amplifier::new(1.0));
+        let c = graph.add_node(Amplifier::new(1.0));
+        graph.connect(a, b).unwrap();
+        graph.connect(b, c).unwrap();
+        graph.connect(c, MASTER_NODE_ID).unwrap();
+
+        assert!(matches!(graph.connect(c, a), Err(Error::Audio(AudioError::AudioGraphCycle(..)))));
+        assert!(matches!(graph.connect(b, a), Err(Error::Audio(AudioError::AudioGraphCycle(..)))));
+        assert!(graph.get_node(a).unwrap().get_inputs().is_empty());
+        graph.connect(a, c).unwrap();
+        assert_eq!(render(&mut graph, 0, 1), [0.0]);
+    }
+
+    #[test]
+    fn modulation_inputs_only_get_their_connections() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let modulated = graph.add_node(ModulatedDevice);
+        let one = graph.add_node(ConstantDevice { values: vec![1.0] });
+        let stereo = graph.add_node(ConstantDevice { values: vec![1.0, 3.0] });
+        graph.connect(one, modulated).unwrap();
+        graph.connect_modulation(one, modulated, "hundreds").unwrap();
+        graph.connect_modulation(stereo, modulated, "tens").unwrap();
+        graph.connect(modulated, MASTER_NODE_ID).unwrap();
+        // the stereo source is mixed down to 2
+        assert_eq!(render(&mut graph, 0, 1), [121.0]);
+        let modulations = [Modulation { source: one, input: 1, sync: false }, Modulation { source: stereo, input: 0, sync: false }];
+        assert_eq!(graph.get_node(modulated).unwrap().get_modulations(), modulations);
+
+        assert!(matches!(graph.connect_modulation(one, modulated, "thousands"), Err(Error::Audio(AudioError::AudioGraphUnknownInput(..)))));
+        assert!(matches!(graph.connect_modulation(one, modulated, "hundreds"), Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(..)))));
+        assert!(matches!(graph.connect_modulation(modulated, modulated, "tens"), Err(Error::Audio(AudioError::AudioGraphCycle(..)))));
+        assert!(matches!(graph.connect_modulation(MASTER_NODE_ID, modulated, "tens"), Err(Error::Audio(AudioError::AudioGraphInvalidConnection(..)))));
+
+        graph.disconnect_modulation(one, modulated, "hundreds").unwrap();
+        assert_eq!(render(&mut graph, 0, 1), [21.0]);
+        assert!(matches!(graph.disconnect_modulation(one, modulated, "hundreds"), Err(Error::Audio(AudioError::AudioGraphNotConnected(..)))));
+        graph.remove_node(stereo).unwrap();
+        assert!(graph.get_node(modulated).unwrap().get_modulations().is_empty());
+        assert_eq!(render(&mut graph, 0, 1), [1.0]);
+    }
+
+    #[test]
+    fn feedback_reads_previous_block() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 1);
+        let one = graph.add_node(CountingDevice::default());
+        let amplifier = graph.add_node(Amplifier::new(0.5));
+        graph.connect(one, amplifier).unwrap();
+        graph.connect_feedback(amplifier, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        // y[n] = 0.5 * (1 + y[n-1])
+        let mut expected = 0.0;
+        for sample in render(&mut graph, 0, 16) {
+            expected = 0.5 * (1.0 + expected);
+            assert_eq!(sample, expected);
+        }
+
+        graph.disconnect_feedback(amplifier, amplifier).unwrap();
+        assert_eq!(render(&mut graph, 16, 1), [0.5]);
+    }
+
+    #[test]
+    fn feedback_allows_deliberate_cycles() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 1);
+        let a = graph.add_node(Amplifier::new(1.0));
+        let b = graph.add_node(Amplifier::new(1.0));
+        graph.connect(a, b).unwrap();
+        graph.connect(b, MASTER_NODE_ID).unwrap();
+
+        assert!(matches!(graph.connect(b, a), Err(Error::Audio(AudioError::AudioGraphCycle(..)))));
+        graph.connect_feedback(b, a).unwrap();
+        assert!(matches!(graph.connect(b, a), Err(Error::Audio(AudioError::AudioGraphAlreadyConnected(..)))));
+        assert_eq!(graph.get_schedule(), &[a, b, MASTER_NODE_ID]);
+
+        // the feedback source is rendered even when it only reaches the master output through feedback
+        let source = graph.add_node(CountingDevice::default());
+        graph.connect_feedback(source, a).unwrap();
+        assert_eq!(render(&mut graph, 0, 3), [0.0, 1.0, 2.0]);
+    }
+
+    /// A couple of oscillators mixed through an amplifier
+    fn oscillator_patch(graph: &mut AudioGraph) -> NodeId {
+        let amplifier = graph.add_node(Amplifier::new(0.25));
+        for i in 1..=3 {
+            let oscillator = graph.add_node(sine_oscillator(110.0 * i as f32));
+            graph.connect(oscillator, amplifier).unwrap();
+        }
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        amplifier
+    }
+
+    #[test]
+    fn block_size_does_not_change_output() {
+        let mut blocks = AudioGraph::with_max_block_size(SAMPLE_RATE, 64);
+        let mut samples = AudioGraph::with_max_block_size(SAMPLE_RATE, 1);
+        oscillator_patch(&mut blocks);
+        oscillator_patch(&mut samples);
+
+        // longer than the max block size and not a multiple of it
+        assert_eq!(render(&mut blocks, 0, 1000), render(&mut samples, 0, 1000));
+    }
+
+    #[test]
+    fn feedback_is_delayed_by_one_block() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 4);
+        let one = graph.add_node(CountingDevice::default());
+        let amplifier = graph.add_node(Amplifier::new(0.5));
+        graph.connect(one, amplifier).unwrap();
+        graph.connect_feedback(amplifier, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        assert_eq!(
+            render(&mut graph, 0, 12),
+            [0.5, 0.5, 0.5, 0.5, 0.75, 0.75, 0.75, 0.75, 0.875, 0.875, 0.875, 0.875]
+        );
+    }
+
+    #[test]
+    fn removed_buffers_are_reused_silent() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 16);
+        let amplifier = oscillator_patch(&mut graph);
+        render(&mut graph, 0, 16);
+
+        graph.remove_node(amplifier).unwrap();
+        let amplifier = graph.add_node(Amplifier::new(1.0));
+        graph.connect_feedback(amplifier, MASTER_NODE_ID).unwrap();
+        assert!(render(&mut graph, 16, 16).iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn mono_sources_are_upmixed() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        graph.set_output_channels(2).unwrap();
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+
+        let output = render(&mut graph, 0, 256);
+        assert_eq!(output.len(), 512);
+        for (time, frame) in output.chunks(2).enumerate() {
+            let expected = expected_sine(time as u64);
+            assert!((frame[0] - expected).abs() < 1e-3, "frame {time}: expected {expected}, got {frame:?}");
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn stereo_flows_through_the_graph() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 8);
+        graph.set_output_channels(2).unwrap();
+        let source = graph.add_node(ConstantDevice { values: vec![1.0, -1.0] });
+        let amplifier = graph.add_node(Amplifier::new(0.5).with_channels(2));
+        graph.connect(source, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        assert_eq!(render(&mut graph, 0, 20), [0.5, -0.5].repeat(20));
+    }
+
+    #[test]
+    fn stereo_is_downmixed_to_mono() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let source = graph.add_node(ConstantDevice { values: vec![1.0, 0.5] });
+        graph.connect(source, MASTER_NODE_ID).unwrap();
+
+        assert_eq!(render(&mut graph, 0, 4), [0.75; 4]);
+    }
+
+    #[test]
+    fn interleaves_any_channel_count() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 3);
+        graph.set_output_channels(6).unwrap();
+        let source = graph.add_node(ConstantDevice { values: vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0] });
+        graph.connect(source, MASTER_NODE_ID).unwrap();
+
+        assert_eq!(render(&mut graph, 0, 10), [0.0, 1.0, 2.0, 3.0, 4.0, 5.0].repeat(10));
+
+        // the connections to the master output survive a channel count change
+        graph.set_output_channels(1).unwrap();
+        assert_eq!(render(&mut graph, 0, 2), [2.5, 2.5]);
+
+        // counts the frames couldn't be laid out in are rejected, not clamped
+        assert!(matches!(graph.set_output_channels(0), Err(Error::Audio(AudioError::AudioGraphInvalidChannels(0)))));
+        assert!(matches!(graph.set_output_channels(40), Err(Error::Audio(AudioError::AudioGraphInvalidChannels(40)))));
+        assert_eq!(graph.get_output_channels(), 1);
+    }
+
+    #[test]
+    fn devices_keep_state_between_blocks() {
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 7);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+
+        // the oscillator carries its phase across blocks instead of deriving it from the time
+        let output = render(&mut graph, 1000, 256);
+        for (time, sample) in output.into_iter().enumerate() {
+            let expected = expected_sine(time as u64);
+            assert!((sample - expected).abs() < 1e-3, "sample {time}: expected {expected}, got {sample}");
+        }
+    }
+
+    #[test]
+    fn devices_are_accessible_by_type() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let oscillator = graph.add_node(sine_oscillator(375.0));
+        let amplifier = graph.add_node(Amplifier::new(1.0));
+        graph.connect(oscillator, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+
+        graph.get_device_mut::<Amplifier>(amplifier).unwrap().set_amplitude(0.5);
+        graph.get_device_mut::<MasterOutput>(MASTER_NODE_ID).unwrap().set_amplitude(0.5);
+        assert_eq!(graph.get_device::<WaveTableOscillator>(oscillator).unwrap().get_frequency(), 375.0);
+        assert!(matches!(graph.get_device::<Amplifier>(oscillator), Err(Error::Audio(AudioError::AudioGraphWrongDeviceType(_)))));
+
+        let output = render(&mut graph, 0, 33);
+        assert!((output[32] - 0.25).abs() < 1e-3);
+    }
+
+    /// Records every call to [`AudioDevice::prepare`]
+    #[derive(Default)]
+    struct PrepareRecorder {
+        calls: Vec<(u32, usize)>,
+    }
+
+    impl AudioDevice for PrepareRecorder {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            0.0
+        }
+
+        fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+            self.calls.push((sample_rate, max_block_size));
+        }
+    }
+
+    #[test]
+    fn devices_are_prepared_when_added_and_on_changes() {
+        let mut graph = AudioGraph::with_max_block_size(44100, 64);
+        let recorder = graph.add_node(PrepareRecorder::default());
+        assert_eq!(graph.get_device::<PrepareRecorder>(recorder).unwrap().calls, [(44100, 64)]);
+
+        graph.prepare(96000, 128);
+        assert_eq!(graph.get_sample_rate(), 96000);
+        assert_eq!(graph.get_max_block_size(), 128);
+        assert_eq!(graph.get_device::<PrepareRecorder>(recorder).unwrap().calls, [(44100, 64), (96000, 128)]);
+
+        graph.replace_device(recorder, PrepareRecorder::default()).unwrap();
+        assert_eq!(graph.get_device::<PrepareRecorder>(recorder).unwrap().calls, [(96000, 128)]);
+    }
+
+    #[test]
+    fn oscillators_follow_the_graph_rate() {
+        for sample_rate in [44100, 48000, 96000] {
+            // created for a different rate, adding it to the graph prepares it for the graph's
+            let mut oscillator = WaveTableOscillator::new(8000, WaveTable::from_waveform(WaveForm::Sine, 128).unwrap());
+            oscillator.set_frequency(sample_rate as f32 / 128.0);
+            oscillator.activate();
+            let mut graph = AudioGraph::with_max_block_size(sample_rate, 100);
+            let oscillator = graph.add_node(oscillator);
+            graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+
+            for (time, sample) in render(&mut graph, 0, 300).into_iter().enumerate() {
+                let expected = expected_sine(time as u64);
+                assert!((sample - expected).abs() < 1e-3, "{sample_rate} Hz, sample {time}: expected {expected}, got {sample}");
+            }
+        }
+    }
+
+    #[test]
+    fn prepare_recomputes_running_oscillators() {
+        for (from, to) in [(48000, 96000), (96000, 44100), (44100, 48000)] {
+            let mut graph = AudioGraph::with_max_block_size(from, 64);
+            let mut oscillator = WaveTableOscillator::new(from, WaveTable::from_waveform(WaveForm::Sine, 128).unwrap());
+            oscillator.set_frequency(1000.0);
+            oscillator.activate();
+            let oscillator = graph.add_node(oscillator);
+            graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+            render(&mut graph, 0, 50);
+
+            graph.prepare(to, 32);
+            let output = render(&mut graph, 50, 1000);
+            // a 1 kHz sine crosses zero upwards once every millisecond, whatever the rate
+            let crossings = output.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count() as f32;
+            let expected = 1000.0 * 1000.0 / to as f32;
+            assert!((crossings - expected).abs() <= 1.0, "{from} -> {to} Hz: {crossings} periods, expected {expected}");
+        }
+    }
+
+    #[test]
+    fn connections_beyond_capacity_are_rejected() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let destination = graph.add_node(Amplifier::new(1.0));
+        for _ in 0..CONNECTION_CAPACITY {
+            let source = graph.add_node(Amplifier::new(1.0));
+            graph.connect(source, destination).unwrap();
+        }
+        let source = graph.add_node(Amplifier::new(1.0));
+        assert!(matches!(graph.connect(source, destination), Err(Error::Audio(AudioError::AudioGraphTooManyConnections(id))) if id == destination));
+        // the other kinds of connections have room of their own
+        graph.connect_feedback(source, destination).unwrap();
+        assert_eq!(graph.get_node(destination).unwrap().get_inputs().len(), CONNECTION_CAPACITY);
+    }
+}
@@ -1,45 +1,285 @@
-use std::sync::Arc;
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
 
-use cpal::{traits::{DeviceTrait, HostTrait}, Device, Host, StreamConfig, SupportedStreamConfig};
-use graph::AudioGraph;
+use backend::{AudioBackend, CpalBackend};
+use command::GraphProcessor;
 
+use crate::{Error, Result, error::AudioError};
+
+pub mod backend;
+pub mod buffer;
+pub mod command;
+pub mod device;
 pub mod error;
 pub mod graph;
+pub mod offline;
+pub mod parameter;
+pub mod wav;
 
-pub struct AudioThread {
-    shared_graph: Arc<Option<AudioGraph>>,
-    host: Host,
-    device: Device,
-    config: SupportedStreamConfig,
+/// Plays the graph of a [`GraphProcessor`] through an [`AudioBackend`], edit it with the matching [`GraphController`](command::GraphController).
+/// The graph keeps playing until [`AudioThread::stop`] is called or the thread is dropped
+pub struct AudioThread<B: AudioBackend = CpalBackend> {
+    processor: Arc<Mutex<GraphProcessor>>, //only locked by the callback while running, so locking never waits
+    backend: B,
+    running: bool,
+    time: Arc<AtomicU64>, //samples rendered so far, carried on when restarting
 }
 
 impl AudioThread {
-    pub fn new(shared_graph: Arc<Option<AudioGraph>>) -> Self {
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("no output device detected");
-        let mut supported_configs_range = device.supported_output_configs()
-        .expect("error while querying configs");
-        let config = supported_configs_range.next()
-            .expect("no supported config")
-            .with_max_sample_rate();
+    /// Plays on the default output device
+    pub fn new(processor: GraphProcessor) -> Result<Self> {
+        Ok(Self::with_backend(processor, CpalBackend::new()?))
+    }
+}
+
+impl<B: AudioBackend> AudioThread<B> {
+    pub fn with_backend(processor: GraphProcessor, backend: B) -> Self {
         Self {
-            shared_graph,
-            host,
-            device,
-            config,
-        }
-    }
-
-    pub fn run(&self) {
-        let stream = self.device.build_output_stream(
-            &self.config.into(),
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                // react to stream events and read or write stream data here.
-            },
-            move |err| {
-                // react to errors here.
-            },
-            None // None=blocking, Some(Duration)=timeout
-        ).expect("failed to build output stream");
-    }
-}
\ No newline at end of file
+            processor: Arc::new(Mutex::new(processor)),
+            backend,
+            running: false,
+            time: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Matches the graph to the backend's channel count and starts rendering it from the audio callback,
+    /// continuing from the time it was stopped at
+    pub fn start(&mut self) -> Result<()> {
+        if self.running {
+            return Err(Error::Audio(AudioError::AudioThreadAlreadyRunning));
+        }
+        let channels = self.backend.channels();
+        // only poisoned if rendering panicked, the graph may be left half edited
+        let mut processor = self.processor.lock().map_err(|_| Error::Audio(AudioError::AudioThreadPoisoned))?;
+        let graph = processor.get_graph_mut();
+        graph.set_output_channels(channels)?;
+        graph.prepare(self.backend.sample_rate(), graph.get_max_block_size());
+        drop(processor);
+
+        let processor = self.processor.clone();
+        let shared_time = self.time.clone();
+        let mut time = shared_time.load(Ordering::Relaxed);
+        self.backend.start(Box::new(move |data: &mut [f32]| {
+            match processor.try_lock() {
+                Ok(mut processor) => processor.process_block(data, time),
+                Err(_) => data.fill(0.0),
+            }
+            time += (data.len() / channels) as u64;
+            shared_time.store(time, Ordering::Relaxed);
+        }))?;
+
+        self.running = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        self.backend.stop()?;
+        self.running = false;
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Samples per channel rendered since the thread was first started
+    pub fn get_time(&self) -> u64 {
+        self.time.load(Ordering::Relaxed)
+    }
+
+    pub fn get_backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn get_backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+}
+
+impl<B: AudioBackend> Drop for AudioThread<B> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{devices::amplifier::Amplifier, error::AudioError, Error, Result};
+    use super::{backend::{AudioBackend, RenderCallback}, command::{command_queue, GraphController, GraphProcessor}, graph::{AudioDevice, AudioGraph, MASTER_NODE_ID}, parameter::ParameterHandle, AudioThread};
+
+    /// Backend without hardware, the test pulls audio from it by hand
+    struct FakeBackend {
+        channels: usize,
+        callback: Option<RenderCallback>,
+    }
+
+    impl FakeBackend {
+        fn new(channels: usize) -> Self {
+            Self {
+                channels,
+                callback: None,
+            }
+        }
+
+        /// Pulls `frames` frames like an output device would, `None` when stopped
+        fn pull(&mut self, frames: usize) -> Option<Vec<f32>> {
+            let callback = self.callback.as_mut()?;
+            let mut data = vec![0.0; frames * self.channels];
+            callback(&mut data);
+            Some(data)
+        }
+    }
+
+    impl AudioBackend for FakeBackend {
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn channels(&self) -> usize {
+            self.channels
+        }
+
+        fn start(&mut self, callback: RenderCallback) -> Result<()> {
+            self.callback = Some(callback);
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            self.callback = None;
+            Ok(())
+        }
+    }
+
+    /// Counts up by one every sample
+    #[derive(Default)]
+    struct Ramp {
+        value: f32,
+    }
+
+    impl AudioDevice for Ramp {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.value += 1.0;
+            self.value
+        }
+    }
+
+    /// Ramp through an amplifier, returns the handle to the amplitude and the id of the amplifier
+    fn ramp_graph() -> (GraphController, GraphProcessor, ParameterHandle, u32) {
+        // prepared for the backend's rate on start
+        let mut graph = AudioGraph::with_max_block_size(44100, 16);
+        let ramp = graph.add_node(Ramp::default());
+        let amplifier = Amplifier::new(1.0);
+        let amplitude = amplifier.amplitude_handle();
+        let amplifier = graph.add_node(amplifier);
+        graph.connect(ramp, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        let (controller, processor) = command_queue(graph, 16);
+        (controller, processor, amplitude, amplifier)
+    }
+
+    #[test]
+    fn callback_renders_graph() {
+        let (_, processor, _, _) = ramp_graph();
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(2));
+        assert!(thread.get_backend_mut().pull(4).is_none());
+
+        thread.start().unwrap();
+        assert!(thread.is_running());
+        let processor = thread.processor.lock().unwrap();
+        assert_eq!(processor.get_graph().get_output_channels(), 2);
+        assert_eq!(processor.get_graph().get_sample_rate(), 48000);
+        drop(processor);
+
+        assert_eq!(thread.get_backend_mut().pull(3).unwrap(), [1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+        // longer than the graph's max block size
+        let data = thread.get_backend_mut().pull(40).unwrap();
+        assert_eq!(data.len(), 80);
+        assert_eq!(data[78..], [43.0, 43.0]);
+    }
+
+    #[test]
+    fn edits_are_heard_at_the_next_callback() {
+        let (mut controller, processor, amplitude, amplifier) = ramp_graph();
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(1));
+        thread.start().unwrap();
+        assert_eq!(thread.get_backend_mut().pull(2).unwrap(), [1.0, 2.0]);
+
+        amplitude.set(0.5);
+        assert_eq!(thread.get_backend_mut().pull(2).unwrap(), [1.5, 2.0]);
+
+        controller.replace_device(amplifier, Amplifier::new(2.0)).unwrap();
+        assert_eq!(thread.get_backend_mut().pull(1).unwrap(), [10.0]);
+        assert!(controller.collect().is_empty());
+        // the old amplifier was sent back, so its handle no longer reaches the graph
+        amplitude.set(0.0);
+        assert_eq!(thread.get_backend_mut().pull(1).unwrap(), [12.0]);
+    }
+
+    #[test]
+    fn start_stop_lifecycle() {
+        let (_, processor, _, _) = ramp_graph();
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(1));
+        thread.start().unwrap();
+        assert!(matches!(thread.start(), Err(Error::Audio(AudioError::AudioThreadAlreadyRunning))));
+
+        thread.stop().unwrap();
+        assert!(!thread.is_running());
+        assert!(thread.get_backend_mut().pull(4).is_none());
+        thread.stop().unwrap();
+
+        // the graph keeps its state across restarts
+        thread.start().unwrap();
+        assert_eq!(thread.get_backend_mut().pull(1).unwrap(), [1.0]);
+        thread.stop().unwrap();
+        thread.start().unwrap();
+        assert_eq!(thread.get_backend_mut().pull(1).unwrap(), [2.0]);
+    }
+
+    /// Outputs the time it's rendered at
+    struct Clock;
+
+    impl AudioDevice for Clock {
+        fn render(&mut self, _inputs: &[f32], time: u64) -> f32 {
+            time as f32
+        }
+    }
+
+    #[test]
+    fn time_continues_after_restarting() {
+        let mut graph = AudioGraph::new(48000);
+        let clock = graph.add_node(Clock);
+        graph.connect(clock, MASTER_NODE_ID).unwrap();
+        let (_, processor) = command_queue(graph, 4);
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(1));
+
+        thread.start().unwrap();
+        assert_eq!(thread.get_backend_mut().pull(3).unwrap(), [0.0, 1.0, 2.0]);
+        thread.stop().unwrap();
+        assert_eq!(thread.get_time(), 3);
+        thread.start().unwrap();
+        assert_eq!(thread.get_backend_mut().pull(2).unwrap(), [3.0, 4.0]);
+        assert_eq!(thread.get_time(), 5);
+    }
+
+    #[test]
+    fn unsupported_channel_counts_are_errors() {
+        let (_, processor, _, _) = ramp_graph();
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(40));
+        assert!(matches!(thread.start(), Err(Error::Audio(AudioError::AudioGraphInvalidChannels(40)))));
+        assert!(!thread.is_running());
+        assert!(thread.get_backend_mut().pull(1).is_none());
+    }
+
+    #[test]
+    fn poisoned_processor_is_an_error() {
+        let (_, processor, _, _) = ramp_graph();
+        let mut thread = AudioThread::with_backend(processor, FakeBackend::new(1));
+        let processor = thread.processor.clone();
+        let _ = std::thread::spawn(move || {
+            let _processor = processor.lock().unwrap();
+            panic!("rendering failed");
+        }).join();
+
+        assert!(matches!(thread.start(), Err(Error::Audio(AudioError::AudioThreadPoisoned))));
+        assert!(!thread.is_running());
+    }
+}
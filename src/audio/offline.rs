@@ -0,0 +1,192 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use crate::{Error, Result, error::AudioError};
+
+use super::{graph::{AudioGraph, MAX_CHANNELS}, wav::{SampleFormat, WavSpec, WavWriter}};
+
+/// Renders an [`AudioGraph`] as fast as possible instead of in real time, without needing an output device
+#[derive(Clone, Copy, Debug)]
+pub struct OfflineRenderer {
+    seconds: f64,
+    channels: usize,
+    format: SampleFormat,
+    sample_rate: Option<u32>, //the graph's own rate if not set
+}
+
+impl OfflineRenderer {
+    /// Renders `seconds` of stereo audio, written as 24 bit
+    pub fn new(seconds: f64) -> Self {
+        Self {
+            seconds,
+            channels: 2,
+            format: SampleFormat::Int24,
+            sample_rate: None,
+        }
+    }
+
+    /// Prepares the graph for `sample_rate` before rendering
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn with_channels(mut self, channels: usize) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_format(mut self, format: SampleFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Length of the render in frames at `sample_rate`
+    pub fn frames(&self, sample_rate: u32) -> u64 {
+        (self.seconds.max(0.0) * sample_rate as f64).round() as u64
+    }
+
+    /// Switches the graph to the rate and channel count of the render.
+    /// The channel count has to be between 1 and [`MAX_CHANNELS`], anything else is an error before rendering anything
+    fn prepare_graph(&self, graph: &mut AudioGraph) -> Result<()> {
+        if let Some(sample_rate) = self.sample_rate {
+            graph.prepare(sample_rate, graph.get_max_block_size());
+        }
+        graph.set_output_channels(self.channels)
+    }
+
+    /// Calls `write` with consecutive blocks of interleaved frames until the render is done, the graph has to be prepared already
+    fn render_blocks(&self, graph: &mut AudioGraph, mut write: impl FnMut(&[f32]) -> Result<()>) -> Result<()> {
+        let channels = graph.get_output_channels();
+        let block_size = graph.get_max_block_size();
+        let mut block = vec![0.0; block_size * channels];
+
+        let frames = self.frames(graph.get_sample_rate());
+        let mut time = 0;
+        while time < frames {
+            let length = (frames - time).min(block_size as u64) as usize;
+            let block = &mut block[..length * channels];
+            graph.process_block(block, time);
+            write(block)?;
+            time += length as u64;
+        }
+        Ok(())
+    }
+
+    /// Renders into memory, returning interleaved frames
+    pub fn render(&self, graph: &mut AudioGraph) -> Result<Vec<f32>> {
+        self.prepare_graph(graph)?;
+        let mut samples = Vec::with_capacity(self.frames(graph.get_sample_rate()) as usize * graph.get_output_channels());
+        self.render_blocks(graph, |block| {
+            samples.extend_from_slice(block);
+            Ok(())
+        })?;
+        Ok(samples)
+    }
+
+    /// Renders straight into a WAV file written to `writer`
+    pub fn render_wav<W: Write>(&self, graph: &mut AudioGraph, writer: W) -> Result<W> {
+        self.prepare_graph(graph)?;
+        let spec = WavSpec {
+            sample_rate: graph.get_sample_rate(),
+            channels: graph.get_output_channels() as u16,
+            format: self.format,
+        };
+        let mut writer = WavWriter::new(writer, spec, self.frames(spec.sample_rate))?;
+        self.render_blocks(graph, |block| writer.write(block))?;
+        writer.finish()
+    }
+
+    /// Renders into a new WAV file at `path`, replacing it if it exists
+    pub fn render_wav_file(&self, graph: &mut AudioGraph, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path).map_err(|err| Error::Audio(AudioError::AudioFileWrite(err.to_string())))?;
+        self.render_wav(graph, BufWriter::new(file))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audio::{graph::{AudioDevice, AudioGraph, MASTER_NODE_ID}, wav::SampleFormat}, devices::amplifier::Amplifier, error::AudioError, Error};
+    use super::OfflineRenderer;
+
+    /// Counts up by one every sample
+    #[derive(Default)]
+    struct Ramp {
+        value: f32,
+    }
+
+    impl AudioDevice for Ramp {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.value += 1.0;
+            self.value
+        }
+    }
+
+    fn ramp_graph(sample_rate: u32) -> AudioGraph {
+        let mut graph = AudioGraph::with_max_block_size(sample_rate, 64);
+        let ramp = graph.add_node(Ramp::default());
+        let amplifier = graph.add_node(Amplifier::new(0.001));
+        graph.connect(ramp, amplifier).unwrap();
+        graph.connect(amplifier, MASTER_NODE_ID).unwrap();
+        graph
+    }
+
+    #[test]
+    fn renders_the_requested_length() {
+        let mut graph = ramp_graph(1000);
+        let samples = OfflineRenderer::new(0.25).with_channels(2).render(&mut graph).unwrap();
+        assert_eq!(samples.len(), 500);
+        for (i, frame) in samples.chunks(2).enumerate() {
+            let expected = (i + 1) as f32 * 0.001;
+            assert!((frame[0] - expected).abs() < 1e-6 && frame[0] == frame[1]);
+        }
+    }
+
+    #[test]
+    fn wav_length_matches_format() {
+        for (format, bytes_per_sample) in [(SampleFormat::Int16, 2), (SampleFormat::Int24, 3), (SampleFormat::Float32, 4)] {
+            let mut graph = ramp_graph(44100);
+            let renderer = OfflineRenderer::new(0.01).with_channels(1).with_format(format);
+            let bytes = renderer.render_wav(&mut graph, Vec::new()).unwrap();
+            let data = bytes.windows(4).position(|id| id == b"data").unwrap();
+            let length = u32::from_le_bytes(bytes[data + 4..data + 8].try_into().unwrap());
+            assert_eq!(length, 441 * bytes_per_sample);
+            assert_eq!(&bytes[8..12], b"WAVE");
+        }
+    }
+
+    #[test]
+    fn renders_at_the_requested_rate() {
+        let mut graph = ramp_graph(48000);
+        let samples = OfflineRenderer::new(0.5).with_channels(1).with_sample_rate(1000).render(&mut graph).unwrap();
+        assert_eq!(samples.len(), 500);
+        assert_eq!(graph.get_sample_rate(), 1000);
+
+        let bytes = OfflineRenderer::new(0.5).with_sample_rate(22050).render_wav(&mut graph, Vec::new()).unwrap();
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 22050);
+    }
+
+    #[test]
+    fn writes_a_file() {
+        let path = std::env::temp_dir().join(format!("offline_render_{}.wav", std::process::id()));
+        let mut graph = ramp_graph(8000);
+        OfflineRenderer::new(1.0).render_wav_file(&mut graph, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+    }
+
+    #[test]
+    fn unsupported_channel_counts_fail_up_front() {
+        for channels in [0, 40] {
+            let mut graph = ramp_graph(1000);
+            let renderer = OfflineRenderer::new(0.1).with_channels(channels);
+            assert!(matches!(renderer.render(&mut graph), Err(Error::Audio(AudioError::AudioGraphInvalidChannels(_)))));
+            let mut written = Vec::new();
+            assert!(matches!(renderer.render_wav(&mut graph, &mut written), Err(Error::Audio(AudioError::AudioGraphInvalidChannels(_)))));
+            // nothing was written before the error
+            assert!(written.is_empty());
+        }
+    }
+}
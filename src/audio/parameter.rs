@@ -0,0 +1,78 @@
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+
+/// A continuous `f32` parameter of a device that can be changed from other threads without locking.
+/// Cloning a parameter copies its value, use [`Parameter::handle`] to change the same value from somewhere else
+#[derive(Debug)]
+pub struct Parameter {
+    value: Arc<AtomicU32>, //bits of the f32
+}
+
+impl Parameter {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value: Arc::new(AtomicU32::new(value.to_bits())),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns a handle sharing the value with this parameter
+    pub fn handle(&self) -> ParameterHandle {
+        ParameterHandle {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl Clone for Parameter {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
+/// Shared access to a [`Parameter`], clones of a handle all refer to the same value
+#[derive(Clone, Debug)]
+pub struct ParameterHandle {
+    value: Arc<AtomicU32>,
+}
+
+impl ParameterHandle {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parameter;
+
+    #[test]
+    fn handles_share_the_value() {
+        let parameter = Parameter::new(1.0);
+        let handle = parameter.handle();
+        let other = handle.clone();
+        other.set(-0.25);
+        assert_eq!(parameter.get(), -0.25);
+        assert_eq!(handle.get(), -0.25);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let parameter = Parameter::new(440.0);
+        let handle = parameter.handle();
+        let copy = parameter.clone();
+        handle.set(220.0);
+        assert_eq!(copy.get(), 440.0);
+        assert_eq!(parameter.get(), 220.0);
+    }
+}
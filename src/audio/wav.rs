@@ -0,0 +1,536 @@
+use std::io::{Read, Write};
+
+use crate::{Error, Result, error::AudioError};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Tail shared by the subformat GUIDs of `WAVE_FORMAT_EXTENSIBLE`, the first two bytes are the format tag
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+
+/// How samples are stored in a WAV file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Offset by 128, the only unsigned format
+    UInt8,
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    pub const ALL: [Self; 6] = [
+        Self::UInt8,
+        Self::Int16,
+        Self::Int24,
+        Self::Int32,
+        Self::Float32,
+        Self::Float64,
+    ];
+
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            Self::UInt8 => 8,
+            Self::Int16 => 16,
+            Self::Int24 => 24,
+            Self::Int32 | Self::Float32 => 32,
+            Self::Float64 => 64,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        self.bits_per_sample() as usize / 8
+    }
+
+    fn format_tag(&self) -> u16 {
+        match self {
+            Self::UInt8 | Self::Int16 | Self::Int24 | Self::Int32 => WAVE_FORMAT_PCM,
+            Self::Float32 | Self::Float64 => WAVE_FORMAT_IEEE_FLOAT,
+        }
+    }
+
+    /// The format stored with `format_tag` in samples of `bits` bits
+    fn from_tag(format_tag: u16, bits: u16) -> Option<Self> {
+        match (format_tag, bits) {
+            (WAVE_FORMAT_PCM, 8) => Some(Self::UInt8),
+            (WAVE_FORMAT_PCM, 16) => Some(Self::Int16),
+            (WAVE_FORMAT_PCM, 24) => Some(Self::Int24),
+            (WAVE_FORMAT_PCM, 32) => Some(Self::Int32),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Some(Self::Float32),
+            (WAVE_FORMAT_IEEE_FLOAT, 64) => Some(Self::Float64),
+            _ => None,
+        }
+    }
+
+    /// Appends `sample` to `bytes`, integer formats clip it to [-1, 1]
+    fn encode(&self, sample: f32, bytes: &mut Vec<u8>) {
+        match self {
+            Self::UInt8 => bytes.push((sample.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8 ^ 0x80),
+            Self::Int16 => {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            },
+            Self::Int24 => {
+                let sample = (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32;
+                bytes.extend_from_slice(&sample.to_le_bytes()[..3]);
+            },
+            Self::Int32 => {
+                let sample = (sample.clamp(-1.0, 1.0) as f64 * i32::MAX as f64).round() as i32;
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            },
+            Self::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            Self::Float64 => bytes.extend_from_slice(&(sample as f64).to_le_bytes()),
+        }
+    }
+
+    /// Reads the sample at the start of `bytes`, the inverse of [`SampleFormat::encode`]
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::UInt8 => (bytes[0] ^ 0x80) as i8 as f32 / 127.0,
+            Self::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32,
+            Self::Int24 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8_388_607.0,
+            Self::Int32 => (i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / i32::MAX as f64) as f32,
+            Self::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            Self::Float64 => f64::from_le_bytes(bytes[..8].try_into().expect("8 bytes")) as f32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: SampleFormat,
+}
+
+impl WavSpec {
+    fn block_align(&self) -> usize {
+        self.channels as usize * self.format.bytes_per_sample()
+    }
+
+    /// Integers above 16 bit and more than two channels need `WAVE_FORMAT_EXTENSIBLE` to be read correctly everywhere
+    fn is_extensible(&self) -> bool {
+        self.channels > 2 || matches!(self.format, SampleFormat::Int24 | SampleFormat::Int32)
+    }
+
+    /// The speaker positions of the first `channels` channels, in the order WAV files store them
+    fn channel_mask(&self) -> u32 {
+        match self.channels {
+            1 => 0x4, //front center
+            channels if channels < 32 => (1 << channels) - 1,
+            _ => 0,
+        }
+    }
+
+    fn fmt_chunk(&self) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(40);
+        let tag = if self.is_extensible() { WAVE_FORMAT_EXTENSIBLE } else { self.format.format_tag() };
+        let block_align = self.block_align() as u16;
+        chunk.extend_from_slice(&tag.to_le_bytes());
+        chunk.extend_from_slice(&self.channels.to_le_bytes());
+        chunk.extend_from_slice(&self.sample_rate.to_le_bytes());
+        chunk.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        chunk.extend_from_slice(&block_align.to_le_bytes());
+        chunk.extend_from_slice(&self.format.bits_per_sample().to_le_bytes());
+        if self.is_extensible() {
+            chunk.extend_from_slice(&22u16.to_le_bytes());
+            chunk.extend_from_slice(&self.format.bits_per_sample().to_le_bytes()); //valid bits
+            chunk.extend_from_slice(&self.channel_mask().to_le_bytes());
+            chunk.extend_from_slice(&self.format.format_tag().to_le_bytes());
+            chunk.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        } else if self.format == SampleFormat::Float32 {
+            chunk.extend_from_slice(&0u16.to_le_bytes());
+        }
+        chunk
+    }
+}
+
+fn write_error(err: std::io::Error) -> Error {
+    Error::Audio(AudioError::AudioFileWrite(err.to_string()))
+}
+
+fn write_chunk_header<W: Write>(writer: &mut W, id: &[u8; 4], length: u32) -> Result<()> {
+    writer.write_all(id).map_err(write_error)?;
+    writer.write_all(&length.to_le_bytes()).map_err(write_error)
+}
+
+/// Streams interleaved samples into a WAV file.
+/// The length has to be known up front so the header can be written first, [`WavWriter::finish`] checks that it was met
+pub struct WavWriter<W: Write> {
+    writer: W,
+    spec: WavSpec,
+    remaining: u64, //samples still to be written
+    data_length: u32,
+    bytes: Vec<u8>, //encoding scratch space
+}
+
+impl<W: Write> WavWriter<W> {
+    /// Writes the header for `frames` frames of audio
+    pub fn new(writer: W, spec: WavSpec, frames: u64) -> Result<Self> {
+        Self::with_chunks(writer, spec, frames, &[])
+    }
+
+    /// Like [`WavWriter::new`], writing `chunks` into the header before the audio
+    pub fn with_chunks(mut writer: W, spec: WavSpec, frames: u64, chunks: &[WavChunk]) -> Result<Self> {
+        if spec.channels == 0 {
+            return Err(Error::Audio(AudioError::AudioWavInvalidFormat));
+        }
+        let samples = frames * spec.channels as u64;
+        let data_length = samples * spec.format.bytes_per_sample() as u64;
+        let fmt = spec.fmt_chunk();
+        let needs_fact = spec.format.format_tag() != WAVE_FORMAT_PCM || spec.is_extensible();
+        let chunks_length: u64 = chunks.iter().map(|chunk| 8 + chunk.data.len() as u64 + chunk.data.len() as u64 % 2).sum();
+        let header_length = 4 + 8 + fmt.len() as u64 + if needs_fact { 12 } else { 0 } + chunks_length + 8;
+        let riff_length = header_length + data_length + data_length % 2;
+        let riff_length = u32::try_from(riff_length).map_err(|_| Error::Audio(AudioError::AudioWavTooLong(frames)))?;
+
+        write_chunk_header(&mut writer, b"RIFF", riff_length)?;
+        writer.write_all(b"WAVE").map_err(write_error)?;
+        write_chunk_header(&mut writer, b"fmt ", fmt.len() as u32)?;
+        writer.write_all(&fmt).map_err(write_error)?;
+        if needs_fact {
+            // frames per channel, required for everything but plain PCM
+            write_chunk_header(&mut writer, b"fact", 4)?;
+            writer.write_all(&(frames as u32).to_le_bytes()).map_err(write_error)?;
+        }
+        for chunk in chunks {
+            write_chunk_header(&mut writer, &chunk.id, chunk.data.len() as u32)?;
+            writer.write_all(&chunk.data).map_err(write_error)?;
+            if chunk.data.len() % 2 == 1 {
+                writer.write_all(&[0]).map_err(write_error)?;
+            }
+        }
+        write_chunk_header(&mut writer, b"data", data_length as u32)?;
+
+        Ok(Self {
+            writer,
+            spec,
+            remaining: samples,
+            data_length: data_length as u32,
+            bytes: Vec::new(),
+        })
+    }
+
+    pub fn get_spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Writes interleaved samples, whole frames don't have to be written at once
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        if samples.len() as u64 > self.remaining {
+            return Err(Error::Audio(AudioError::AudioWavWrongLength(self.remaining, samples.len() as u64)));
+        }
+        self.bytes.clear();
+        for sample in samples {
+            self.spec.format.encode(*sample, &mut self.bytes);
+        }
+        self.writer.write_all(&self.bytes).map_err(write_error)?;
+        self.remaining -= samples.len() as u64;
+        Ok(())
+    }
+
+    /// Pads the data chunk and flushes, returning the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        if self.remaining != 0 {
+            return Err(Error::Audio(AudioError::AudioWavWrongLength(self.remaining, 0)));
+        }
+        if self.data_length % 2 == 1 {
+            self.writer.write_all(&[0]).map_err(write_error)?;
+        }
+        self.writer.flush().map_err(write_error)?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes interleaved `samples` as a complete WAV file
+pub fn write_wav<W: Write>(writer: W, spec: WavSpec, samples: &[f32]) -> Result<W> {
+    let frames = samples.len() as u64 / spec.channels.max(1) as u64;
+    let mut writer = WavWriter::new(writer, spec, frames)?;
+    writer.write(samples)?;
+    writer.finish()
+}
+
+/// A chunk of a WAV file that isn't needed to read the audio, like `clm ` or `LIST`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WavChunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// A WAV file read into memory
+#[derive(Clone, Debug, PartialEq)]
+pub struct WavFile {
+    pub spec: WavSpec,
+    pub samples: Vec<f32>, //interleaved
+    pub chunks: Vec<WavChunk>, //every chunk besides fmt, fact and data, in file order
+}
+
+impl WavFile {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.spec.channels as usize
+    }
+
+    pub fn get_chunk(&self, id: &[u8; 4]) -> Option<&WavChunk> {
+        self.chunks.iter().find(|chunk| &chunk.id == id)
+    }
+
+    /// Writes the samples and every chunk as a complete WAV file, the chunks go before the audio
+    pub fn write<W: Write>(&self, writer: W) -> Result<W> {
+        let mut writer = WavWriter::with_chunks(writer, self.spec, self.frames() as u64, &self.chunks)?;
+        writer.write(&self.samples)?;
+        writer.finish()
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn chunk_name(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+/// Parses the `fmt ` chunk, resolving `WAVE_FORMAT_EXTENSIBLE` to the format of its subformat
+fn parse_fmt(fmt: &[u8]) -> Result<WavSpec> {
+    if fmt.len() < 16 {
+        return Err(Error::Audio(AudioError::AudioWavInvalidFormat));
+    }
+    let (mut tag, channels, sample_rate, block_align, bits) = (u16_at(fmt, 0), u16_at(fmt, 2), u32_at(fmt, 4), u16_at(fmt, 12), u16_at(fmt, 14));
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 40 {
+            return Err(Error::Audio(AudioError::AudioWavInvalidFormat));
+        }
+        tag = u16_at(fmt, 24);
+    }
+    let format = SampleFormat::from_tag(tag, bits)
+        .ok_or(Error::Audio(AudioError::AudioWavUnsupportedFormat(tag, bits)))?;
+    let spec = WavSpec {
+        sample_rate,
+        channels,
+        format,
+    };
+    if channels == 0 || sample_rate == 0 || block_align as usize != spec.block_align() {
+        return Err(Error::Audio(AudioError::AudioWavInvalidFormat));
+    }
+    Ok(spec)
+}
+
+/// Reads a complete WAV file from `reader`, keeping the chunks it doesn't need in [`WavFile::chunks`]
+pub fn read_wav<R: Read>(mut reader: R) -> Result<WavFile> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|err| Error::Audio(AudioError::AudioFileRead(err.to_string())))?;
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(Error::Audio(AudioError::AudioWavNotWave));
+    }
+
+    let (mut spec, mut data, mut chunks) = (None, None, Vec::new());
+    let mut offset = 12;
+    // a stray byte at the end is left over padding, not a chunk
+    while offset + 8 <= bytes.len() {
+        let id: [u8; 4] = bytes[offset..offset + 4].try_into().expect("4 bytes");
+        let length = u32_at(&bytes, offset + 4) as usize;
+        let start = offset + 8;
+        let body = bytes.get(start..start + length)
+            .ok_or(Error::Audio(AudioError::AudioWavTruncated(chunk_name(&id))))?;
+        match &id {
+            b"fmt " => spec = Some(parse_fmt(body)?),
+            b"data" => data = Some(body),
+            b"fact" => (),
+            _ => chunks.push(WavChunk {
+                id,
+                data: body.to_vec(),
+            }),
+        }
+        offset = start + length + length % 2;
+    }
+
+    let spec = spec.ok_or(Error::Audio(AudioError::AudioWavMissingChunk(String::from("fmt "))))?;
+    let data = data.ok_or(Error::Audio(AudioError::AudioWavMissingChunk(String::from("data"))))?;
+    // a partial frame at the end is dropped
+    let block_align = spec.block_align();
+    let bytes_per_sample = spec.format.bytes_per_sample();
+    let samples = data[..data.len() - data.len() % block_align]
+        .chunks_exact(bytes_per_sample)
+        .map(|sample| spec.format.decode(sample))
+        .collect();
+
+    Ok(WavFile {
+        spec,
+        samples,
+        chunks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::AudioError, Error};
+    use super::{read_wav, u16_at, u32_at, write_wav, SampleFormat, WavChunk, WavFile, WavSpec, WavWriter};
+
+    /// Offsets of every chunk after the RIFF header by id
+    fn chunks(bytes: &[u8]) -> Vec<([u8; 4], usize, usize)> {
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+        while offset < bytes.len() {
+            let id = bytes[offset..offset + 4].try_into().unwrap();
+            let length = u32_at(bytes, offset + 4) as usize;
+            chunks.push((id, offset + 8, length));
+            offset += 8 + length + length % 2;
+        }
+        assert_eq!(offset, bytes.len());
+        chunks
+    }
+
+    #[test]
+    fn pcm_16_header() {
+        let spec = WavSpec { sample_rate: 44100, channels: 2, format: SampleFormat::Int16 };
+        let bytes = write_wav(Vec::new(), spec, &[0.0, 1.0, -1.0, 0.5]).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"WAVE");
+
+        let chunks = chunks(&bytes);
+        assert_eq!(chunks.iter().map(|chunk| &chunk.0).collect::<Vec<_>>(), [b"fmt ", b"data"]);
+        let (_, fmt, length) = chunks[0];
+        assert_eq!(length, 16);
+        assert_eq!(u16_at(&bytes, fmt), 1);
+        assert_eq!(u16_at(&bytes, fmt + 2), 2);
+        assert_eq!(u32_at(&bytes, fmt + 4), 44100);
+        assert_eq!(u32_at(&bytes, fmt + 8), 44100 * 4);
+        assert_eq!(u16_at(&bytes, fmt + 12), 4);
+        assert_eq!(u16_at(&bytes, fmt + 14), 16);
+
+        let (_, data, length) = chunks[1];
+        let samples: Vec<i16> = bytes[data..data + length].chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, 16384]);
+    }
+
+    #[test]
+    fn pcm_24_is_extensible_and_padded() {
+        let spec = WavSpec { sample_rate: 48000, channels: 1, format: SampleFormat::Int24 };
+        let bytes = write_wav(Vec::new(), spec, &[0.5, -2.0, 0.25]).unwrap();
+        let chunks = chunks(&bytes);
+        assert_eq!(chunks.iter().map(|chunk| &chunk.0).collect::<Vec<_>>(), [b"fmt ", b"fact", b"data"]);
+
+        let (_, fmt, length) = chunks[0];
+        assert_eq!(length, 40);
+        assert_eq!(u16_at(&bytes, fmt), 0xfffe);
+        assert_eq!(u16_at(&bytes, fmt + 14), 24);
+        assert_eq!(u16_at(&bytes, fmt + 18), 24);
+        assert_eq!(u16_at(&bytes, fmt + 24), 1); //PCM subformat
+
+        let (_, data, length) = chunks[2];
+        assert_eq!(length, 9);
+        assert_eq!(bytes.len() % 2, 0);
+        let samples: Vec<i32> = bytes[data..data + length].chunks(3).map(|bytes| i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8).collect();
+        assert_eq!(samples, [4_194_304, -8_388_607, 2_097_152]);
+    }
+
+    #[test]
+    fn float_has_fact_chunk() {
+        let spec = WavSpec { sample_rate: 96000, channels: 2, format: SampleFormat::Float32 };
+        let samples = [0.1, -0.2, 1.5, -1.5];
+        let bytes = write_wav(Vec::new(), spec, &samples).unwrap();
+        let chunks = chunks(&bytes);
+
+        let (_, fmt, length) = chunks[0];
+        assert_eq!(length, 18);
+        assert_eq!(u16_at(&bytes, fmt), 3);
+        let (id, fact, _) = chunks[1];
+        assert_eq!(&id, b"fact");
+        assert_eq!(u32_at(&bytes, fact), 2);
+
+        let (_, data, length) = chunks[2];
+        let read: Vec<f32> = bytes[data..data + length].chunks(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn wrong_length_is_an_error() {
+        let spec = WavSpec { sample_rate: 48000, channels: 2, format: SampleFormat::Int16 };
+        let mut writer = WavWriter::new(Vec::new(), spec, 2).unwrap();
+        writer.write(&[0.0; 3]).unwrap();
+        assert!(matches!(writer.write(&[0.0; 2]), Err(Error::Audio(AudioError::AudioWavWrongLength(1, 2)))));
+        assert!(matches!(writer.finish(), Err(Error::Audio(AudioError::AudioWavWrongLength(1, 0)))));
+    }
+
+    #[test]
+    fn no_channels_is_an_error() {
+        let spec = WavSpec { sample_rate: 48000, channels: 0, format: SampleFormat::Int16 };
+        assert!(matches!(WavWriter::new(Vec::new(), spec, 2), Err(Error::Audio(AudioError::AudioWavInvalidFormat))));
+        assert!(matches!(write_wav(Vec::new(), spec, &[0.0; 4]), Err(Error::Audio(AudioError::AudioWavInvalidFormat))));
+    }
+
+    #[test]
+    fn reads_back_every_format() {
+        let samples = [0.0, 0.5, -0.5, 1.0, -1.0, 0.123];
+        for format in SampleFormat::ALL {
+            for channels in [1, 2, 3] {
+                let spec = WavSpec { sample_rate: 22050, channels, format };
+                let samples = &samples[..samples.len() / channels as usize * channels as usize];
+                let file = read_wav(write_wav(Vec::new(), spec, samples).unwrap().as_slice()).unwrap();
+                assert_eq!(file.spec, spec);
+                assert_eq!(file.frames(), samples.len() / channels as usize);
+                assert!(file.chunks.is_empty());
+                let tolerance = if format == SampleFormat::UInt8 { 1e-2 } else { 1e-4 };
+                for (read, written) in file.samples.iter().zip(samples) {
+                    assert!((read - written).abs() < tolerance, "{format:?}: wrote {written}, read {read}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_chunks() {
+        let spec = WavSpec { sample_rate: 44100, channels: 1, format: SampleFormat::Int16 };
+        let mut bytes = write_wav(Vec::new(), spec, &[0.25; 3]).unwrap();
+        bytes.extend_from_slice(b"abc \x03\x00\x00\x00xyz\x00");
+        let riff_length = bytes.len() as u32 - 8;
+        bytes[4..8].copy_from_slice(&riff_length.to_le_bytes());
+
+        let file = read_wav(bytes.as_slice()).unwrap();
+        assert_eq!(file.chunks, [WavChunk { id: *b"abc ", data: b"xyz".to_vec() }]);
+        assert_eq!(file.get_chunk(b"abc ").unwrap().data, b"xyz");
+        assert_eq!(file.samples.len(), 3);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let spec = WavSpec { sample_rate: 44100, channels: 2, format: SampleFormat::Int16 };
+        let bytes = write_wav(Vec::new(), spec, &[0.5; 8]).unwrap();
+        let read = |bytes: &[u8]| read_wav(bytes).unwrap_err();
+
+        assert!(matches!(read(b"RIFX\0\0\0\0WAVE"), Error::Audio(AudioError::AudioWavNotWave)));
+        assert!(matches!(read(&bytes[..bytes.len() - 4]), Error::Audio(AudioError::AudioWavTruncated(id)) if id == "data"));
+        // only the RIFF header and the fmt chunk
+        assert!(matches!(read(&bytes[..36]), Error::Audio(AudioError::AudioWavMissingChunk(id)) if id == "data"));
+
+        let mut adpcm = bytes.clone();
+        adpcm[20..22].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(read(&adpcm), Error::Audio(AudioError::AudioWavUnsupportedFormat(2, 16))));
+
+        let mut no_channels = bytes.clone();
+        no_channels[22..24].copy_from_slice(&0u16.to_le_bytes());
+        assert!(matches!(read(&no_channels), Error::Audio(AudioError::AudioWavInvalidFormat)));
+    }
+
+    #[test]
+    fn writes_chunks_before_the_data() {
+        let file = WavFile {
+            spec: WavSpec { sample_rate: 44100, channels: 1, format: SampleFormat::Float32 },
+            samples: vec![0.5, -0.5, 0.25],
+            chunks: vec![WavChunk { id: *b"clm ", data: b"<!>3".to_vec() }, WavChunk { id: *b"odd ", data: b"xyz".to_vec() }],
+        };
+        let bytes = file.write(Vec::new()).unwrap();
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        let ids: Vec<_> = chunks(&bytes).into_iter().map(|chunk| chunk.0).collect();
+        assert_eq!(ids, [*b"fmt ", *b"fact", *b"clm ", *b"odd ", *b"data"]);
+        assert_eq!(read_wav(bytes.as_slice()).unwrap(), file);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{error::Error, process::ExitCode};
+
+use wavetable_synthesizer::{audio::{graph::{AudioGraph, MASTER_NODE_ID}, offline::OfflineRenderer, wav::SampleFormat}, devices::{amplifier::Amplifier, oscillator::WaveTableOscillator}, synthesis::{expression::Expression, waveforms::WaveForm, wavetable::{WaveTable, DEFAULT_TABLE_LENGTH}}};
+
+const USAGE: &str = "\
+Renders an oscillator through an amplifier into a WAV file, without an audio device
+
+Usage: render <output.wav> [options]
+
+Options:
+    --seconds <seconds>        length of the render [default: 2]
+    --sample-rate <hz>         [default: 48000]
+    --bits <16|24|32>          32 writes float samples [default: 24]
+    --channels <count>         [default: 2]
+    --waveform <name>          sine, triangle, square, saw, pulse, skewed-triangle, sine-to-square,
+                               half-rectified-sine, full-rectified-sine or trapezoid [default: saw]
+    --shape <amount>           parameter of pulse, skewed-triangle, sine-to-square and trapezoid, in [0, 1]
+    --expression <text>        waveform as a function of x in radians, like \"sin(x) + 0.3 * sin(3 * x)\"
+    --frequency <hz>           [default: 110]
+    --gain <amplitude>         [default: 0.7]";
+
+struct Options {
+    output: String,
+    seconds: f64,
+    sample_rate: u32,
+    format: SampleFormat,
+    channels: usize,
+    waveform: WaveForm,
+    shape: Option<f32>,
+    expression: Option<Expression>, //replaces the waveform
+    frequency: f32,
+    gain: f32,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, Box<dyn Error>> {
+    let mut output = None;
+    let mut options = Options {
+        output: String::new(),
+        seconds: 2.0,
+        sample_rate: 48000,
+        format: SampleFormat::Int24,
+        channels: 2,
+        waveform: WaveForm::Saw,
+        shape: None,
+        expression: None,
+        frequency: 110.0,
+        gain: 0.7,
+    };
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if output.replace(arg).is_some() {
+                return Err("more than one output file given".into());
+            }
+            continue;
+        }
+        let value = args.next().ok_or(format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "--seconds" => options.seconds = value.parse()?,
+            "--sample-rate" => options.sample_rate = value.parse()?,
+            "--bits" => {
+                options.format = match value.as_str() {
+                    "16" => SampleFormat::Int16,
+                    "24" => SampleFormat::Int24,
+                    "32" => SampleFormat::Float32,
+                    _ => return Err(format!("unsupported bit depth {value}").into()),
+                }
+            },
+            "--channels" => options.channels = value.parse()?,
+            "--waveform" => {
+                options.waveform = WaveForm::ALL.into_iter()
+                    .find(|waveform| waveform.get_name().replace(' ', "-").eq_ignore_ascii_case(&value))
+                    .ok_or(format!("unknown waveform {value}"))?
+            },
+            "--shape" => options.shape = Some(value.parse()?),
+            "--expression" => {
+                let expression = Expression::parse(&value).map_err(|err| format!("invalid expression: {err:?}"))?;
+                options.expression = Some(expression);
+            },
+            "--frequency" => options.frequency = value.parse()?,
+            "--gain" => options.gain = value.parse()?,
+            _ => return Err(format!("unknown option {arg}").into()),
+        }
+    }
+
+    options.output = output.ok_or("no output file given")?;
+    if let Some(shape) = options.shape {
+        options.waveform = options.waveform.with_parameter(shape);
+    }
+    if options.sample_rate == 0 || options.channels == 0 {
+        return Err("sample rate and channel count have to be above 0".into());
+    }
+    Ok(options)
+}
+
+fn render(options: &Options) -> Result<(), Box<dyn Error>> {
+    let wavetable = match &options.expression {
+        Some(expression) => expression.to_wavetable(DEFAULT_TABLE_LENGTH, 1),
+        None => WaveTable::from_waveform(options.waveform, DEFAULT_TABLE_LENGTH)?,
+    };
+    let mut oscillator = WaveTableOscillator::new(options.sample_rate, wavetable);
+    oscillator.set_frequency(options.frequency);
+    oscillator.activate();
+
+    // the graph prepares the oscillator for its rate
+    let mut graph = AudioGraph::new(options.sample_rate);
+    let oscillator = graph.add_node(oscillator);
+    let amplifier = graph.add_node(Amplifier::new(options.gain));
+    graph.connect(oscillator, amplifier)?;
+    graph.connect(amplifier, MASTER_NODE_ID)?;
+
+    OfflineRenderer::new(options.seconds)
+        .with_channels(options.channels)
+        .with_format(options.format)
+        .render_wav_file(&mut graph, &options.output)?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    let options = match parse_options(args.into_iter()) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        },
+    };
+    if let Err(err) = render(&options) {
+        eprintln!("error while rendering: {err:?}");
+        return ExitCode::FAILURE;
+    }
+    println!("wrote {}", options.output);
+    ExitCode::SUCCESS
+}
@@ -1,29 +1,54 @@
-use crate::audio::graph::{render_nodes, AudioDevice, AudioNode};
+use crate::audio::{graph::{mix_inputs, AudioDevice, ProcessContext}, parameter::{Parameter, ParameterHandle}};
 
 #[derive(Clone, Debug)]
 pub struct Amplifier {
-    amplitude: f32,
+    amplitude: Parameter,
+    channels: usize,
 }
 
 impl Amplifier {
     pub fn new(amplitude: f32) -> Self {
         Self {
-            amplitude,
+            amplitude: Parameter::new(amplitude),
+            channels: 1,
         }
     }
 
+    pub fn with_channels(mut self, channels: usize) -> Self {
+        self.channels = channels;
+        self
+    }
+
     pub fn get_amplitude(&self) -> f32 {
-        self.amplitude
+        self.amplitude.get()
     }
 
     pub fn set_amplitude(&mut self, amplitude: f32) {
-        self.amplitude = amplitude;
+        self.amplitude.set(amplitude);
+    }
+
+    /// For changing the amplitude while the amplifier is playing
+    pub fn amplitude_handle(&self) -> ParameterHandle {
+        self.amplitude.handle()
     }
 }
 
 impl AudioDevice for Amplifier {
-    fn render(&self, children: &Vec<AudioNode>, time: u64) -> f32 {
-        let input = render_nodes(children, time);
-        input * self.amplitude 
+    fn render(&mut self, inputs: &[f32], _time: u64) -> f32 {
+        let input = mix_inputs(inputs);
+        input * self.amplitude.get()
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], _context: &ProcessContext) {
+        let amplitude = self.amplitude.get();
+        for (output, input) in outputs.iter_mut().zip(inputs) {
+            for (sample, input) in output.iter_mut().zip(input.iter()) {
+                *sample = input * amplitude;
+            }
+        }
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
     }
 }
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod oscillator;
-pub mod amplifier;
\ No newline at end of file
+pub mod amplifier;
+pub mod unison;
\ No newline at end of file
@@ -1,27 +1,76 @@
-use std::{f32::consts::TAU, sync::Arc};
+use std::{any::Any, f64::consts::TAU};
 
-use crate::{audio::graph::{AudioDevice, AudioNode}, synthesis::{waveforms::WaveForm, wavetable::WaveTable}};
+use crate::{audio::{command::DeviceEdit, graph::{AudioDevice, ProcessContext, DEFAULT_MAX_BLOCK_SIZE}, parameter::{Parameter, ParameterHandle}}, error::AudioError, synthesis::{interpolation::Interpolation, phase::{wrap, PhaseAccumulator}, waveforms::WaveForm, wavetable::{MipLevel, WaveTable}}, Error, Result};
 
+use super::unison::UnisonOscillator;
+
+/// Modulation input adding to the frame position every sample
+pub const POSITION_INPUT: &str = "position";
+/// Modulation input adding `amount` times the frequency for an input of 1, negative frequencies run the phase backwards
+pub const LINEAR_FM_INPUT: &str = "linear fm";
+/// Modulation input changing the pitch by `amount` semitones for an input of 1
+pub const PITCH_FM_INPUT: &str = "pitch fm";
+/// Modulation input moving the phase the table is read at by `amount` radians for an input of 1
+pub const PHASE_MODULATION_INPUT: &str = "phase modulation";
+/// Modulation input for the wraps of another oscillator, see [`AudioGraph::connect_sync`](crate::audio::graph::AudioGraph::connect_sync)
+pub const SYNC_INPUT: &str = "sync";
+
+const MODULATION_INPUTS: [&str; 5] = [POSITION_INPUT, LINEAR_FM_INPUT, PITCH_FM_INPUT, PHASE_MODULATION_INPUT, SYNC_INPUT];
+
+/// What a [`WaveTableOscillator`] does when the oscillator at its [`SYNC_INPUT`] wraps around
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Restarts its cycle
+    #[default]
+    Hard,
+    /// Reverses the direction its phase moves in
+    Soft,
+}
+
+/// Plays a [`WaveTable`] at a frequency. The frame position, frequency and phase are modulated through the named inputs,
+/// see [`AudioGraph::connect_modulation`](crate::audio::graph::AudioGraph::connect_modulation). Its main input goes unused
 #[derive(Clone, Debug)]
 pub struct WaveTableOscillator {
     active: bool,
-    sample_rate: Arc<u32>,
+    sample_rate: u32,
     wavetable: WaveTable,
-    phase_increment: f32,
+    phase: PhaseAccumulator,
+    mip_level: MipLevel, //band-limited levels of the wavetable that fit the frequency
+    interpolation: Option<Interpolation>, //overrides the wavetable's own mode, kept across table changes
     amplitude: f32,
-    frequency: f32,
+    frequency: Parameter,
+    position: Parameter, //where in the frames of the wavetable to read, in [0, 1]
+    linear_fm: Parameter, //amounts of the modulation inputs
+    pitch_fm: Parameter,
+    phase_modulation: Parameter,
+    sync_mode: SyncMode,
+    direction: f64, //1 or -1, reversed by soft sync
+    sync_correction: f32, //second half of the correction for a sync in the previous sample
+    sync_output: Vec<f32>, //where the phase wrapped in every sample of the last block
+    rendered_frequency: f32, //the frequency the phase increment was computed for
 }
 
 impl WaveTableOscillator {
-    pub fn new(sample_rate: Arc<u32>, wavetable: WaveTable) -> Self 
+    pub fn new(sample_rate: u32, wavetable: WaveTable) -> Self 
     {       
         Self {
             active: false,
             sample_rate,
             wavetable,
-            phase_increment: 0.0,
+            phase: PhaseAccumulator::default(),
+            mip_level: MipLevel::default(),
+            interpolation: None,
             amplitude: 1.0,
-            frequency: 0.0,
+            frequency: Parameter::new(0.0),
+            position: Parameter::new(0.0),
+            linear_fm: Parameter::new(1.0),
+            pitch_fm: Parameter::new(1.0),
+            phase_modulation: Parameter::new(1.0),
+            sync_mode: SyncMode::default(),
+            direction: 1.0,
+            sync_correction: 0.0,
+            sync_output: vec![0.0; DEFAULT_MAX_BLOCK_SIZE],
+            rendered_frequency: 0.0,
         }
     }
 
@@ -34,7 +83,7 @@ impl WaveTableOscillator {
     }
 
     pub fn get_sample_rate(&self) -> u32 {
-        (*self.sample_rate).clone()
+        self.sample_rate
     }
 
     pub fn set_amplitude(&mut self, amplitude: f32) {
@@ -42,12 +91,99 @@ impl WaveTableOscillator {
     }
 
     pub fn set_frequency(&mut self, frequency: f32) {
-        self.phase_increment = frequency*TAU / (self.get_sample_rate() as f32);
-        self.frequency = frequency;
+        self.frequency.set(frequency);
+        self.update_frequency();
     }
 
     pub fn get_frequency(&self) -> f32 {
-        self.frequency
+        self.frequency.get()
+    }
+
+    /// For changing the frequency while the oscillator is playing, picked up at the start of the next block
+    pub fn frequency_handle(&self) -> ParameterHandle {
+        self.frequency.handle()
+    }
+
+    pub fn set_position(&mut self, position: f32) {
+        self.position.set(position);
+    }
+
+    pub fn get_position(&self) -> f32 {
+        self.position.get()
+    }
+
+    /// For moving through the frames while the oscillator is playing, picked up at the start of the next block
+    pub fn position_handle(&self) -> ParameterHandle {
+        self.position.handle()
+    }
+
+    pub fn set_linear_fm(&mut self, amount: f32) {
+        self.linear_fm.set(amount);
+    }
+
+    pub fn get_linear_fm(&self) -> f32 {
+        self.linear_fm.get()
+    }
+
+    /// Depth of the [`LINEAR_FM_INPUT`], picked up at the start of the next block
+    pub fn linear_fm_handle(&self) -> ParameterHandle {
+        self.linear_fm.handle()
+    }
+
+    pub fn set_pitch_fm(&mut self, semitones: f32) {
+        self.pitch_fm.set(semitones);
+    }
+
+    pub fn get_pitch_fm(&self) -> f32 {
+        self.pitch_fm.get()
+    }
+
+    /// Depth of the [`PITCH_FM_INPUT`], picked up at the start of the next block
+    pub fn pitch_fm_handle(&self) -> ParameterHandle {
+        self.pitch_fm.handle()
+    }
+
+    pub fn set_phase_modulation(&mut self, radians: f32) {
+        self.phase_modulation.set(radians);
+    }
+
+    pub fn get_phase_modulation(&self) -> f32 {
+        self.phase_modulation.get()
+    }
+
+    /// Depth of the [`PHASE_MODULATION_INPUT`], picked up at the start of the next block
+    pub fn phase_modulation_handle(&self) -> ParameterHandle {
+        self.phase_modulation.handle()
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    fn update_frequency(&mut self) {
+        let frequency = self.frequency.get();
+        if frequency != self.rendered_frequency {
+            self.rendered_frequency = frequency;
+            self.update_phase_increment();
+        }
+    }
+
+    fn update_phase_increment(&mut self) {
+        self.phase.set_frequency(self.rendered_frequency as f64, self.sample_rate);
+        self.mip_level = self.wavetable.mip_level(self.phase.get_increment());
+    }
+
+    /// Current position in the waveform in cycles, in [0, 1)
+    pub fn get_phase(&self) -> f64 {
+        self.phase.get_phase()
+    }
+
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase.set_phase(phase);
     }
 
     pub fn get_waveform(&self) -> WaveForm {
@@ -56,26 +192,506 @@ impl WaveTableOscillator {
 
     pub fn set_wavetable(&mut self, wavetable: WaveTable) {
         self.wavetable = wavetable;
+        self.wavetable_changed();
+    }
+
+    /// The mode the wavetable is currently read with
+    pub fn get_interpolation(&self) -> Interpolation {
+        self.wavetable.get_interpolation()
+    }
+
+    /// Reads every wavetable this oscillator plays with `interpolation`, `None` leaves the mode to the tables
+    pub fn set_interpolation(&mut self, interpolation: Option<Interpolation>) {
+        self.interpolation = interpolation;
+        if let Some(interpolation) = interpolation {
+            self.wavetable.set_interpolation(interpolation);
+        }
+    }
+
+    fn wavetable_changed(&mut self) {
+        if let Some(interpolation) = self.interpolation {
+            self.wavetable.set_interpolation(interpolation);
+        }
+        self.update_phase_increment();
     }
 
-    /*
     pub fn get_sample(&mut self) -> f32 {
+        self.get_sample_at(self.position.get())
+    }
+
+    /// Like [`WaveTableOscillator::get_sample`] reading the frames at `position` instead of the position parameter
+    pub fn get_sample_at(&mut self, position: f32) -> f32 {
         if !self.active {
             return 0.0;
         }
-        let sample = self.wavetable.lookup(self.phase);
-        self.phase += self.phase_increment;
-        self.phase %= TAU;
-        sample*self.amplitude
-    }*/
+        let (increment, mip_level) = (self.phase.get_increment(), self.mip_level);
+        self.step(position, increment, mip_level, 0.0, 0.0).0*self.amplitude
+    }
+
+    /// Like [`WaveTableOscillator::get_sample_at`] with the increment and the phase modulated for this sample only,
+    /// `linear_fm` in multiples of the frequency, `pitch_fm` in semitones and `phase_modulation` in radians.
+    /// `sync` is where in this sample the oscillator at the sync input wrapped, 0 if it didn't.
+    /// Returns the sample and where in it this oscillator wrapped
+    fn get_modulated_sample(&mut self, position: f32, linear_fm: f32, pitch_fm: f32, phase_modulation: f32, sync: f32) -> (f32, f32) {
+        if !self.active {
+            return (0.0, 0.0);
+        }
+        let base = self.phase.get_increment();
+        let mut increment = base;
+        if pitch_fm != 0.0 {
+            increment *= (pitch_fm as f64 / 12.0).exp2();
+        }
+        increment += base * linear_fm as f64;
+        // the levels follow the modulated pitch, deep modulation would alias otherwise
+        let mip_level = if increment == base { self.mip_level } else { self.wavetable.mip_level(increment) };
+        let (sample, wrapped) = self.step(position, increment, mip_level, phase_modulation as f64 / TAU, sync);
+        (sample*self.amplitude, wrapped)
+    }
+
+    /// Reads the next sample at the phase moved by `offset` cycles, then moves the phase on by `increment`.
+    /// A sync jumps or bends the waveform between two samples, the jump is smoothed over both of them with a polyBLEP
+    /// and the bend with a polyBLAMP so sync sweeps don't alias
+    fn step(&mut self, position: f32, increment: f64, mip_level: MipLevel, offset: f64, sync: f32) -> (f32, f32) {
+        let read = |phase: f64| if offset == 0.0 { phase } else { wrap(phase + offset) };
+        let increment = increment * self.direction;
+        let phase = self.phase.get_phase();
+        let sample = self.wavetable.lookup_band_limited(read(phase), mip_level, position) + self.sync_correction;
+        self.sync_correction = 0.0;
+        if sync <= 0.0 {
+            let wrapped = self.phase.wrap_position(increment).unwrap_or(0.0);
+            self.phase.advance_by(increment);
+            return (sample, wrapped_output(wrapped));
+        }
+
+        let sync = sync.min(1.0) as f64;
+        let before = self.phase.wrap_position(increment * sync);
+        self.phase.advance_by(increment * sync);
+        let synced = self.phase.get_phase();
+        let (jump, bend, wrapped) = match self.sync_mode {
+            SyncMode::Hard => {
+                let jump = self.wavetable.lookup_band_limited(read(0.0), mip_level, position)
+                    - self.wavetable.lookup_band_limited(read(synced), mip_level, position);
+                let bend = (self.slope(read(0.0), mip_level, position) - self.slope(read(synced), mip_level, position)) * increment as f32;
+                self.phase.set_phase(0.0);
+                self.phase.advance_by(increment * (1.0 - sync));
+                // restarting counts as a wrap for oscillators synced to this one
+                (jump, bend, sync)
+            },
+            SyncMode::Soft => {
+                self.direction = -self.direction;
+                let after = self.phase.wrap_position(-increment * (1.0 - sync));
+                self.phase.advance_by(-increment * (1.0 - sync));
+                let wrapped = before.map(|wrapped| wrapped * sync)
+                    .or(after.map(|wrapped| sync + wrapped * (1.0 - sync)))
+                    .unwrap_or(0.0);
+                // the phase turns around, so only the slope flips
+                (0.0, -2.0 * self.slope(read(synced), mip_level, position) * increment as f32, wrapped)
+            },
+        };
+
+        // how far the samples before and after are from the sync, in samples
+        let (distance_before, distance_after) = ((1.0 - sync) as f32, sync as f32);
+        let correction = jump * distance_before.powi(2) / 2.0 + bend * distance_before.powi(3) / 6.0;
+        self.sync_correction = -jump * distance_after.powi(2) / 2.0 + bend * distance_after.powi(3) / 6.0;
+        (sample + correction, wrapped_output(wrapped))
+    }
+
+    /// Slope of the waveform at `phase`, per cycle
+    fn slope(&self, phase: f64, mip_level: MipLevel, position: f32) -> f32 {
+        let step = 1.0 / self.wavetable.len() as f64;
+        let difference = self.wavetable.lookup_band_limited(wrap(phase + step), mip_level, position)
+            - self.wavetable.lookup_band_limited(wrap(phase - step), mip_level, position);
+        difference / (2.0 * step) as f32
+    }
+}
+
+/// A wrap position for [`AudioDevice::sync_output`], where 0 means there was none
+fn wrapped_output(wrapped: f64) -> f32 {
+    if wrapped == 0.0 {
+        return 0.0;
+    }
+    (wrapped as f32).max(f32::MIN_POSITIVE)
 }
 
 impl AudioDevice for WaveTableOscillator {
-    fn render(&self, _children: &Vec<AudioNode>, time: u64) -> f32 {
-        if !self.active {
-            return 0.0; 
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.sync_output.resize(max_block_size, 0.0);
+        self.update_phase_increment();
+    }
+
+    fn render(&mut self, inputs: &[f32], _time: u64) -> f32 {
+        self.update_frequency();
+        // the modulation inputs come after the main input
+        let input = |index: usize| inputs.get(1 + index).copied().unwrap_or(0.0);
+        self.get_modulated_sample(
+            self.position.get() + input(0),
+            input(1) * self.linear_fm.get(),
+            input(2) * self.pitch_fm.get(),
+            input(3) * self.phase_modulation.get(),
+            input(4),
+        ).0
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], _context: &ProcessContext) {
+        self.update_frequency();
+        let position = self.position.get();
+        let (linear_fm, pitch_fm, phase_modulation) = (self.linear_fm.get(), self.pitch_fm.get(), self.phase_modulation.get());
+        // the modulation inputs come after the main input
+        let input = |index: usize| inputs.get(1 + index).copied().unwrap_or(&[]);
+        let (offsets, linear, pitch, phase, sync) = (input(0), input(1), input(2), input(3), input(4));
+        for (i, sample) in outputs[0].iter_mut().enumerate() {
+            let at = |input: &[f32]| input.get(i).copied().unwrap_or(0.0);
+            let (output, wrapped) = self.get_modulated_sample(position + at(offsets), at(linear) * linear_fm, at(pitch) * pitch_fm, at(phase) * phase_modulation, at(sync));
+            *sample = output;
+            if let Some(sync_output) = self.sync_output.get_mut(i) {
+                *sync_output = wrapped;
+            }
         }
-        let phase = ((time as f32) * self.phase_increment) % TAU;
-        self.wavetable.lookup(phase)
     }
-}
\ No newline at end of file
+
+    fn modulation_inputs(&self) -> &'static [&'static str] {
+        &MODULATION_INPUTS
+    }
+
+    fn sync_output(&self) -> Option<&[f32]> {
+        Some(&self.sync_output)
+    }
+}
+
+/// Swaps the wavetable of a playing [`WaveTableOscillator`] or [`UnisonOscillator`], the old table comes back in the edit to be dropped off the audio thread
+pub struct SetWavetable {
+    wavetable: WaveTable,
+}
+
+impl SetWavetable {
+    pub fn new(wavetable: WaveTable) -> Self {
+        Self {
+            wavetable,
+        }
+    }
+}
+
+impl DeviceEdit for SetWavetable {
+    fn apply(&mut self, device: &mut dyn AudioDevice) -> Result<()> {
+        let device: &mut dyn Any = device;
+        if let Some(unison) = device.downcast_mut::<UnisonOscillator>() {
+            unison.swap_wavetable(&mut self.wavetable);
+            return Ok(());
+        }
+        let oscillator = device.downcast_mut::<WaveTableOscillator>()
+            .ok_or(Error::Audio(AudioError::AudioEditWrongDeviceType))?;
+        std::mem::swap(&mut oscillator.wavetable, &mut self.wavetable);
+        oscillator.wavetable_changed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use crate::{audio::graph::{AudioDevice, AudioGraph, ProcessContext, MASTER_NODE_ID}, devices::amplifier::Amplifier, error::AudioError, synthesis::{fft::real_fft, interpolation::Interpolation, waveforms::WaveForm, wavetable::{WaveTable, DEFAULT_TABLE_LENGTH}}, Error};
+    use super::{SyncMode, WaveTableOscillator, PHASE_MODULATION_INPUT, POSITION_INPUT, SYNC_INPUT};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(frequency: f32) -> WaveTableOscillator {
+        let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, WaveTable::from_waveform(WaveForm::Sine, 4096).unwrap());
+        oscillator.set_frequency(frequency);
+        oscillator.activate();
+        oscillator
+    }
+
+    fn process(oscillator: &mut WaveTableOscillator, length: usize) -> Vec<f32> {
+        let mut output = vec![0.0; length];
+        let context = ProcessContext { sample_rate: SAMPLE_RATE, time: 0 };
+        oscillator.process(&[], &mut [&mut output], &context);
+        output
+    }
+
+    #[test]
+    fn frequency_changes_keep_the_phase() {
+        let mut oscillator = sine(1000.0);
+        let mut output = process(&mut oscillator, 100);
+        oscillator.frequency_handle().set(3000.0);
+        output.extend(process(&mut oscillator, 100));
+
+        for (time, sample) in output.into_iter().enumerate() {
+            let time = time as f64 / SAMPLE_RATE as f64;
+            let cycles = 1000.0 * time.min(100.0 / SAMPLE_RATE as f64) + 3000.0 * (time - 100.0 / SAMPLE_RATE as f64).max(0.0);
+            let expected = (TAU * cycles).sin() as f32;
+            assert!((sample - expected).abs() < 1e-4, "expected {expected}, got {sample}");
+        }
+    }
+
+    #[test]
+    fn sweeps_have_no_discontinuities() {
+        let mut oscillator = sine(20.0);
+        let frequency = oscillator.frequency_handle();
+        let mut output = Vec::new();
+        for block in 0..200 {
+            // jumps of up to 1 kHz between blocks
+            frequency.set(20.0 + 997.0 * (block % 11) as f32);
+            output.extend(process(&mut oscillator, 32));
+        }
+
+        // a sine can't move further between samples than its steepest slope allows
+        let max_step = (TAU * 10000.0 / SAMPLE_RATE as f64) as f32;
+        for pair in output.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= max_step * 1.01, "jump from {} to {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn prepare_keeps_the_phase() {
+        let mut oscillator = sine(440.0);
+        process(&mut oscillator, 1234);
+        let phase = oscillator.get_phase();
+        oscillator.prepare(96000, 512);
+        assert_eq!(oscillator.get_phase(), phase);
+        assert_eq!(oscillator.get_sample_rate(), 96000);
+    }
+
+    /// Share of the energy in `output` that isn't at a multiple of `fundamental_bin`, in decibel
+    fn aliased_energy(output: &[f32], fundamental_bin: usize) -> f64 {
+        let spectrum = real_fft(output);
+        let (mut harmonic, mut aliased) = (0.0, 0.0);
+        for (bin, value) in spectrum.iter().enumerate().take(output.len() / 2).skip(1) {
+            let energy = value.magnitude().powi(2);
+            if bin % fundamental_bin == 0 {
+                harmonic += energy;
+            } else {
+                aliased += energy;
+            }
+        }
+        10.0 * (aliased / (harmonic + aliased)).log10()
+    }
+
+    #[test]
+    fn aliasing_stays_low_across_the_pitch_range() {
+        const LENGTH: usize = 8192;
+        for waveform in [WaveForm::Saw, WaveForm::Square] {
+            let wavetable = WaveTable::from_waveform(waveform, DEFAULT_TABLE_LENGTH).unwrap();
+            // odd bins so aliases can't land on harmonics, which exactly fill the analysis window
+            for bin in [19, 75, 171, 427, 853, 1365, 2049, 2731, 3243] {
+                let frequency = bin as f32 * SAMPLE_RATE as f32 / LENGTH as f32;
+                let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, wavetable.clone());
+                oscillator.set_frequency(frequency);
+                oscillator.activate();
+                let output = process(&mut oscillator, LENGTH);
+
+                let aliased = aliased_energy(&output, bin);
+                assert!(aliased < -60.0, "{waveform} at {frequency} Hz: {aliased} dB aliased");
+            }
+        }
+    }
+
+    #[test]
+    fn aliasing_is_measurable_without_band_limiting() {
+        // reading the fullest level regardless of pitch, like a single naive table
+        let wavetable = WaveTable::from_waveform(WaveForm::Saw, DEFAULT_TABLE_LENGTH).unwrap();
+        let increment = 853.0 / 8192.0;
+        let output: Vec<f32> = (0..8192).map(|i| wavetable.lookup_normalized((i as f64 * increment).fract())).collect();
+        assert!(aliased_energy(&output, 853) > -40.0);
+    }
+
+    #[test]
+    fn interpolation_override_survives_table_changes() {
+        // a short table, so the interpolation error is well above the noise floor
+        let short_sine = || WaveTable::from_waveform(WaveForm::Sine, 32).unwrap();
+        let error = |oscillator: &mut WaveTableOscillator| {
+            oscillator.set_phase(0.0);
+            process(oscillator, 4800).iter().enumerate()
+                .map(|(time, sample)| (sample - (TAU * 997.0 * time as f64 / SAMPLE_RATE as f64).sin() as f32).abs())
+                .fold(0.0, f32::max)
+        };
+
+        let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, short_sine());
+        oscillator.set_frequency(997.0);
+        oscillator.activate();
+        let linear = error(&mut oscillator);
+
+        oscillator.set_interpolation(Some(Interpolation::Sinc));
+        oscillator.set_wavetable(short_sine());
+        assert_eq!(oscillator.get_interpolation(), Interpolation::Sinc);
+        let sinc = error(&mut oscillator);
+        assert!(sinc < linear / 100.0, "linear {linear}, sinc {sinc}");
+    }
+
+    #[test]
+    fn position_is_modulated_every_sample() {
+        // a sine fading into its inverse
+        let wavetable = WaveTable::from_frames_fn(|position, phase| (1.0 - 2.0 * position) * phase.sin(), 1024, 2);
+        let mut oscillator = WaveTableOscillator::new(SAMPLE_RATE, wavetable);
+        oscillator.set_frequency(1000.0);
+        oscillator.activate();
+        let expected = |time: usize, gain: f32| gain * (TAU * 1000.0 * time as f64 / SAMPLE_RATE as f64).sin() as f32;
+
+        let modulation: Vec<f32> = (0..480).map(|i| i as f32 / 480.0).collect();
+        let mut output = vec![0.0; 480];
+        let context = ProcessContext { sample_rate: SAMPLE_RATE, time: 0 };
+        // the unused main input, then the position input
+        oscillator.process(&[&[0.0; 480], &modulation], &mut [&mut output], &context);
+        for (time, (sample, position)) in output.iter().zip(modulation.iter()).enumerate() {
+            assert!((sample - expected(time, 1.0 - 2.0 * position)).abs() < 1e-3, "{sample} at {time}");
+        }
+
+        oscillator.position_handle().set(1.0);
+        for (time, sample) in process(&mut oscillator, 480).iter().enumerate() {
+            assert!((sample - expected(480 + time, -1.0)).abs() < 1e-3, "{sample} at {}", 480 + time);
+        }
+        assert_eq!(oscillator.get_position(), 1.0);
+
+        // the same input through the graph
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        oscillator.set_position(0.0);
+        oscillator.set_phase(0.0);
+        let oscillator = graph.add_node(oscillator);
+        let offset = graph.add_node(Constant(1.0));
+        graph.connect_modulation(offset, oscillator, POSITION_INPUT).unwrap();
+        graph.connect(oscillator, MASTER_NODE_ID).unwrap();
+        let mut output = vec![0.0; 480];
+        graph.process_block(&mut output, 0);
+        for (time, sample) in output.iter().enumerate() {
+            assert!((sample - expected(time, -1.0)).abs() < 1e-3, "{sample} at {time}");
+        }
+    }
+
+    /// Outputs the same value every sample
+    struct Constant(f32);
+
+    impl AudioDevice for Constant {
+        fn render(&mut self, _inputs: &[f32], _time: u64) -> f32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn modulation_inputs_change_frequency_and_phase() {
+        let expected = |time: usize, frequency: f64, offset: f64| (TAU * frequency * time as f64 / SAMPLE_RATE as f64 + offset).sin() as f32;
+        let context = ProcessContext { sample_rate: SAMPLE_RATE, time: 0 };
+        let (zeros, ones) = (vec![0.0; 480], vec![1.0; 480]);
+        let render = |oscillator: &mut WaveTableOscillator, inputs: [&[f32]; 5]| {
+            let mut output = vec![0.0; 480];
+            oscillator.process(&inputs, &mut [&mut output], &context);
+            output
+        };
+        let check = |output: Vec<f32>, frequency: f64, offset: f64| {
+            for (time, sample) in output.iter().enumerate() {
+                assert!((sample - expected(time, frequency, offset)).abs() < 1e-3, "{sample} at {time}");
+            }
+        };
+
+        // an octave up
+        let mut oscillator = sine(500.0);
+        oscillator.set_pitch_fm(12.0);
+        check(render(&mut oscillator, [&zeros, &zeros, &zeros, &ones, &zeros]), 1000.0, 0.0);
+
+        // through zero, running backwards
+        let mut oscillator = sine(500.0);
+        oscillator.linear_fm_handle().set(2.0);
+        let minus_one = vec![-1.0; 480];
+        check(render(&mut oscillator, [&zeros, &zeros, &minus_one, &zeros, &zeros]), -500.0, 0.0);
+
+        let mut oscillator = sine(500.0);
+        oscillator.set_phase_modulation(std::f32::consts::FRAC_PI_2);
+        check(render(&mut oscillator, [&zeros, &zeros, &zeros, &zeros, &ones]), 500.0, TAU / 4.0);
+        // the phase itself never moved
+        check(render(&mut oscillator, [&zeros, &zeros, &zeros, &zeros, &zeros]), 500.0, TAU * 500.0 * 480.0 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn phase_modulation_through_the_graph() {
+        let mut graph = AudioGraph::new(SAMPLE_RATE);
+        let modulator = graph.add_node(sine(300.0));
+        let mut carrier = sine(600.0);
+        carrier.set_phase_modulation(2.0);
+        let carrier = graph.add_node(carrier);
+        graph.connect_modulation(modulator, carrier, PHASE_MODULATION_INPUT).unwrap();
+        graph.connect(carrier, MASTER_NODE_ID).unwrap();
+
+        let mut output = vec![0.0; 1024];
+        graph.process_block(&mut output, 0);
+        for (time, sample) in output.iter().enumerate() {
+            let time = time as f64 / SAMPLE_RATE as f64;
+            let expected = (TAU * 600.0 * time + 2.0 * (TAU * 300.0 * time).sin()).sin() as f32;
+            assert!((sample - expected).abs() < 1e-3, "{sample} instead of {expected}");
+        }
+    }
+
+    #[test]
+    fn hard_sync_restarts_with_the_master() {
+        // a single block, so the sync output covers all of it
+        let mut graph = AudioGraph::with_max_block_size(SAMPLE_RATE, 1440);
+        // wraps every 480 samples
+        let master = graph.add_node(sine(100.0));
+        let mut slave = WaveTableOscillator::new(SAMPLE_RATE, WaveTable::from_waveform(WaveForm::Saw, DEFAULT_TABLE_LENGTH).unwrap());
+        slave.set_frequency(237.0);
+        slave.activate();
+        let slave = graph.add_node(slave);
+        graph.connect_sync(master, slave, SYNC_INPUT).unwrap();
+        graph.connect(slave, MASTER_NODE_ID).unwrap();
+
+        let amplifier = graph.add_node(Amplifier::new(1.0));
+        assert!(matches!(graph.connect_sync(amplifier, slave, SYNC_INPUT), Err(Error::Audio(AudioError::AudioGraphNoSyncOutput(..)))));
+
+        let mut output = vec![0.0; 1440];
+        graph.process_block(&mut output, 0);
+        // a cycle of the master holds the same two and a bit cycles of the slave every time
+        for time in 480..960 {
+            assert!((output[time] - output[time + 480]).abs() < 1e-3, "{} and {} at {time}", output[time], output[time + 480]);
+        }
+        // and restarting counts as a wrap of the slave
+        let wraps = graph.get_device::<WaveTableOscillator>(slave).unwrap().sync_output().unwrap();
+        let wrapped = |around: usize| wraps[around - 2..around + 2].iter().any(|wrapped| *wrapped > 0.0);
+        assert!(wrapped(480) && wrapped(960));
+    }
+
+    #[test]
+    fn sync_is_band_limited() {
+        const LENGTH: usize = 8192;
+        let context = ProcessContext { sample_rate: SAMPLE_RATE, time: 0 };
+        let zeros = vec![0.0; LENGTH];
+        let saw = WaveTable::from_waveform(WaveForm::Saw, DEFAULT_TABLE_LENGTH).unwrap();
+        // everything synced to the master repeats with it, so anything between its harmonics is aliasing
+        let bin = 172;
+        let frequency = bin as f32 * SAMPLE_RATE as f32 / LENGTH as f32;
+        let mut master = sine(frequency);
+        master.prepare(SAMPLE_RATE, LENGTH);
+        process(&mut master, LENGTH);
+        let wraps = master.sync_output().unwrap().to_vec();
+
+        let slave = || {
+            let mut slave = WaveTableOscillator::new(SAMPLE_RATE, saw.clone());
+            slave.set_frequency(2.37 * frequency);
+            slave.activate();
+            slave
+        };
+        let render = |sync_mode: SyncMode| {
+            let mut slave = slave();
+            slave.set_sync_mode(sync_mode);
+            let mut output = vec![0.0; LENGTH];
+            slave.process(&[&zeros, &zeros, &zeros, &zeros, &zeros, &wraps], &mut [&mut output], &context);
+            output
+        };
+
+        // the same phase resets without any correction
+        let mut naive = slave();
+        let increment = 2.37 * frequency as f64 / SAMPLE_RATE as f64;
+        let naive: Vec<f32> = wraps.iter().map(|wrapped| {
+            let sample = naive.get_sample();
+            if *wrapped > 0.0 {
+                naive.set_phase(increment * (1.0 - *wrapped as f64));
+            }
+            sample
+        }).collect();
+
+        let (naive, hard) = (aliased_energy(&naive, bin), aliased_energy(&render(SyncMode::Hard), bin));
+        assert!(hard < naive - 10.0, "{hard} dB aliased with, {naive} dB without correction");
+
+        // soft sync runs a cycle of the master forwards and the next one backwards, so it repeats every other cycle
+        let soft = aliased_energy(&render(SyncMode::Soft), bin / 2);
+        assert!(soft < -55.0, "{soft} dB aliased");
+    }
+}
@@ -0,0 +1,550 @@
+use crate::{audio::{graph::{AudioDevice, ProcessContext}, parameter::{Parameter, ParameterHandle}}, synthesis::{phase::PhaseAccumulator, waveforms::WaveForm, wavetable::{MipLevel, WaveTable}}};
+
+use super::oscillator::POSITION_INPUT;
+
+pub const MAX_VOICES: usize = 16;
+
+const MODULATION_INPUTS: [&str; 1] = [POSITION_INPUT];
+
+/// Time a voice takes to fade in or out, so changing the voice count doesn't click
+const VOICE_FADE_SECONDS: f32 = 0.005;
+
+/// How the voices are spread between the lowest and the highest detune
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DetuneCurve {
+    /// Evenly spaced
+    #[default]
+    Linear,
+    /// Pulled towards the centre pitch, so most voices only beat slowly
+    Center,
+    /// Pushed towards the outermost detune
+    Edges,
+}
+
+impl DetuneCurve {
+    pub const ALL: [Self; 3] = [
+        Self::Linear,
+        Self::Center,
+        Self::Edges,
+    ];
+
+    /// Maps the place of a voice in [-1, 1] to its share of the detune, also in [-1, 1]
+    fn apply(&self, spread: f32) -> f32 {
+        match self {
+            Self::Linear => spread,
+            Self::Center => spread * spread.abs(),
+            Self::Edges => spread.signum() * spread.abs().sqrt(),
+        }
+    }
+}
+
+/// Where a voice starts in its cycle when it starts sounding
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoicePhase {
+    /// A different random phase for every voice, the usual unison sound
+    Random,
+    /// Every voice at the same phase in cycles, so they start out in unison and drift apart
+    Fixed(f64),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Voice {
+    phase: PhaseAccumulator,
+    mip_level: MipLevel,
+    gains: [f32; 2], //left and right, fade towards the targets so neither level nor panning jump
+    targets: [f32; 2],
+}
+
+impl Voice {
+    fn is_silent(&self) -> bool {
+        self.gains == [0.0; 2] && self.targets == [0.0; 2]
+    }
+}
+
+/// Settings the voices were last computed for
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Settings {
+    frequency: f32,
+    voices: usize,
+    detune: f32,
+    blend: f32,
+    width: f32,
+}
+
+/// A [`WaveTable`] played by up to [`MAX_VOICES`] detuned voices spread across two channels.
+/// Like [`WaveTableOscillator`](super::oscillator::WaveTableOscillator) its [`POSITION_INPUT`] modulates the frame position every sample
+#[derive(Clone, Debug)]
+pub struct UnisonOscillator {
+    active: bool,
+    sample_rate: u32,
+    wavetable: WaveTable,
+    voices: [Voice; MAX_VOICES],
+    amplitude: f32,
+    frequency: Parameter,
+    position: Parameter,
+    voice_count: Parameter, //rounded and clamped to [1, MAX_VOICES]
+    detune: Parameter, //cents between the lowest and the highest voice
+    blend: Parameter, //0 only the centre voices, 0.5 all voices equally loud, 1 only the side voices
+    width: Parameter, //0 mono, 1 the outermost voices panned hard left and right
+    detune_curve: DetuneCurve,
+    voice_phase: VoicePhase,
+    random_state: u32,
+    rendered: Option<Settings>, //none if the voices have to be recomputed
+}
+
+impl UnisonOscillator {
+    pub fn new(sample_rate: u32, wavetable: WaveTable) -> Self {
+        Self {
+            active: false,
+            sample_rate,
+            wavetable,
+            voices: [Voice::default(); MAX_VOICES],
+            amplitude: 1.0,
+            frequency: Parameter::new(0.0),
+            position: Parameter::new(0.0),
+            voice_count: Parameter::new(1.0),
+            detune: Parameter::new(20.0),
+            blend: Parameter::new(0.5),
+            width: Parameter::new(1.0),
+            detune_curve: DetuneCurve::default(),
+            voice_phase: VoicePhase::Random,
+            random_state: 0x2545_f491,
+            rendered: None,
+        }
+    }
+
+    pub fn with_voices(self, voices: usize) -> Self {
+        self.voice_count.set(voices as f32);
+        self
+    }
+
+    pub fn with_detune(self, cents: f32) -> Self {
+        self.detune.set(cents);
+        self
+    }
+
+    pub fn with_detune_curve(mut self, detune_curve: DetuneCurve) -> Self {
+        self.detune_curve = detune_curve;
+        self
+    }
+
+    pub fn with_voice_phase(mut self, voice_phase: VoicePhase) -> Self {
+        self.voice_phase = voice_phase;
+        self
+    }
+
+    /// Seeds the random voice phases, so renders can be repeated exactly
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.random_state = seed.max(1);
+        self
+    }
+
+    pub fn activate(&mut self) {
+        self.active = true;
+    }
+
+    pub fn decativate(&mut self) {
+        self.active = false;
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude = amplitude;
+    }
+
+    pub fn get_frequency(&self) -> f32 {
+        self.frequency.get()
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency.set(frequency);
+    }
+
+    /// Picked up at the start of the next block, like every other handle of the oscillator
+    pub fn frequency_handle(&self) -> ParameterHandle {
+        self.frequency.handle()
+    }
+
+    pub fn get_position(&self) -> f32 {
+        self.position.get()
+    }
+
+    pub fn set_position(&mut self, position: f32) {
+        self.position.set(position);
+    }
+
+    pub fn position_handle(&self) -> ParameterHandle {
+        self.position.handle()
+    }
+
+    pub fn get_voices(&self) -> usize {
+        voice_count(self.voice_count.get())
+    }
+
+    pub fn set_voices(&mut self, voices: usize) {
+        self.voice_count.set(voices as f32);
+    }
+
+    /// Takes the voice count as a float, so it can share the knob code of the other parameters
+    pub fn voices_handle(&self) -> ParameterHandle {
+        self.voice_count.handle()
+    }
+
+    pub fn get_detune(&self) -> f32 {
+        self.detune.get()
+    }
+
+    pub fn set_detune(&mut self, cents: f32) {
+        self.detune.set(cents);
+    }
+
+    pub fn detune_handle(&self) -> ParameterHandle {
+        self.detune.handle()
+    }
+
+    pub fn get_blend(&self) -> f32 {
+        self.blend.get()
+    }
+
+    pub fn set_blend(&mut self, blend: f32) {
+        self.blend.set(blend);
+    }
+
+    pub fn blend_handle(&self) -> ParameterHandle {
+        self.blend.handle()
+    }
+
+    pub fn get_width(&self) -> f32 {
+        self.width.get()
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width.set(width);
+    }
+
+    pub fn width_handle(&self) -> ParameterHandle {
+        self.width.handle()
+    }
+
+    pub fn get_detune_curve(&self) -> DetuneCurve {
+        self.detune_curve
+    }
+
+    pub fn set_detune_curve(&mut self, detune_curve: DetuneCurve) {
+        self.detune_curve = detune_curve;
+        self.rendered = None;
+    }
+
+    pub fn get_voice_phase(&self) -> VoicePhase {
+        self.voice_phase
+    }
+
+    /// Only applies to voices that start sounding afterwards, see [`UnisonOscillator::reset_phases`]
+    pub fn set_voice_phase(&mut self, voice_phase: VoicePhase) {
+        self.voice_phase = voice_phase;
+    }
+
+    /// Restarts every voice at its initial phase
+    pub fn reset_phases(&mut self) {
+        for i in 0..MAX_VOICES {
+            let phase = self.initial_phase();
+            self.voices[i].phase.set_phase(phase);
+        }
+    }
+
+    pub fn get_waveform(&self) -> WaveForm {
+        self.wavetable.get_waveform()
+    }
+
+    pub fn set_wavetable(&mut self, wavetable: WaveTable) {
+        self.wavetable = wavetable;
+        self.rendered = None;
+    }
+
+    /// For [`SetWavetable`](super::oscillator::SetWavetable), the old table ends up in `wavetable`
+    pub(crate) fn swap_wavetable(&mut self, wavetable: &mut WaveTable) {
+        std::mem::swap(&mut self.wavetable, wavetable);
+        self.rendered = None;
+    }
+
+    /// xorshift, good enough for phases and doesn't allocate
+    fn initial_phase(&mut self) -> f64 {
+        match self.voice_phase {
+            VoicePhase::Fixed(phase) => phase,
+            VoicePhase::Random => {
+                self.random_state ^= self.random_state << 13;
+                self.random_state ^= self.random_state >> 17;
+                self.random_state ^= self.random_state << 5;
+                self.random_state as f64 / (u32::MAX as f64 + 1.0)
+            },
+        }
+    }
+
+    /// Recomputes pitch, gain and panning of every voice if any of the parameters changed since the last block
+    fn update_voices(&mut self) {
+        let settings = Settings {
+            frequency: self.frequency.get(),
+            voices: voice_count(self.voice_count.get()),
+            detune: self.detune.get(),
+            blend: self.blend.get().clamp(0.0, 1.0),
+            width: self.width.get().clamp(0.0, 1.0),
+        };
+        if self.rendered == Some(settings) {
+            return;
+        }
+        self.rendered = Some(settings);
+
+        // nothing sounds yet, so the first voices start at full gain instead of fading in
+        let first = self.voices.iter().all(Voice::is_silent);
+        let count = settings.voices;
+        let mut power = 0.0;
+        for i in 0..count {
+            let spread = if count == 1 { 0.0 } else { 2.0 * i as f32 / (count - 1) as f32 - 1.0 };
+            let cents = self.detune_curve.apply(spread) * settings.detune / 2.0;
+            let frequency = settings.frequency as f64 * 2f64.powf(cents as f64 / 1200.0);
+
+            // the middle voice, or the middle two for even counts
+            let center = count <= 2 || i == count / 2 || (count.is_multiple_of(2) && i + 1 == count / 2);
+            let weight = if center { 2.0 * (1.0 - settings.blend) } else { 2.0 * settings.blend };
+            let weight = if count <= 2 { 1.0 } else { weight.min(1.0) };
+            power += weight * weight;
+
+            let pan = spread * settings.width;
+            let starting = self.voices[i].is_silent();
+            let phase = if starting { Some(self.initial_phase()) } else { None };
+            let voice = &mut self.voices[i];
+            voice.phase.set_frequency(frequency, self.sample_rate);
+            voice.mip_level = self.wavetable.mip_level(voice.phase.get_increment());
+            if let Some(phase) = phase {
+                voice.phase.set_phase(phase);
+            }
+            voice.targets = [weight * (1.0 - pan).min(1.0), weight * (1.0 + pan).min(1.0)];
+        }
+        // about equally loud for any count, detuned voices add up by power
+        let normalize = if power > 0.0 { 1.0 / power.sqrt() } else { 0.0 };
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            let normalize = if i < count { normalize } else { 0.0 };
+            voice.targets = voice.targets.map(|target| target * normalize);
+            if first {
+                voice.gains = voice.targets;
+            }
+        }
+    }
+
+    /// The next stereo frame with the frames read at `position`
+    fn next_frame(&mut self, position: f32, fade_step: f32) -> (f32, f32) {
+        let (mut left, mut right) = (0.0, 0.0);
+        for voice in self.voices.iter_mut() {
+            if voice.is_silent() {
+                continue;
+            }
+            let sample = self.wavetable.lookup_band_limited(voice.phase.get_phase(), voice.mip_level, position);
+            voice.phase.advance();
+            left += sample * voice.gains[0];
+            right += sample * voice.gains[1];
+            for (gain, target) in voice.gains.iter_mut().zip(voice.targets) {
+                if *gain < target {
+                    *gain = (*gain + fade_step).min(target);
+                } else if *gain > target {
+                    *gain = (*gain - fade_step).max(target);
+                }
+            }
+        }
+        (left * self.amplitude, right * self.amplitude)
+    }
+
+    fn fade_step(&self) -> f32 {
+        1.0 / (VOICE_FADE_SECONDS * self.sample_rate as f32)
+    }
+}
+
+fn voice_count(voices: f32) -> usize {
+    (voices.round().max(1.0) as usize).min(MAX_VOICES)
+}
+
+impl AudioDevice for UnisonOscillator {
+    fn prepare(&mut self, sample_rate: u32, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.rendered = None;
+    }
+
+    /// Both channels mixed down, for mono use
+    fn render(&mut self, inputs: &[f32], _time: u64) -> f32 {
+        if !self.active {
+            return 0.0;
+        }
+        self.update_voices();
+        // the position input comes after the unused main input
+        let modulation = inputs.get(1).copied().unwrap_or(0.0);
+        let (left, right) = self.next_frame(self.position.get() + modulation, self.fade_step());
+        0.5 * (left + right)
+    }
+
+    fn process(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], _context: &ProcessContext) {
+        if !self.active {
+            for output in outputs.iter_mut() {
+                output.fill(0.0);
+            }
+            return;
+        }
+        self.update_voices();
+        let (position, fade_step) = (self.position.get(), self.fade_step());
+        let modulation = inputs.get(1).copied().unwrap_or(&[]);
+        let (left, right) = outputs.split_at_mut(1);
+        for (i, left) in left[0].iter_mut().enumerate() {
+            let (l, r) = self.next_frame(position + modulation.get(i).copied().unwrap_or(0.0), fade_step);
+            *left = l;
+            if let Some(right) = right.first_mut() {
+                right[i] = r;
+            }
+        }
+    }
+
+    fn channels(&self) -> usize {
+        2
+    }
+
+    fn input_channels(&self) -> usize {
+        1
+    }
+
+    fn modulation_inputs(&self) -> &'static [&'static str] {
+        &MODULATION_INPUTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audio::graph::{AudioDevice, ProcessContext}, devices::oscillator::WaveTableOscillator, synthesis::{waveforms::WaveForm, wavetable::WaveTable}};
+    use super::{DetuneCurve, UnisonOscillator, VoicePhase, MAX_VOICES};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn unison(voices: usize) -> UnisonOscillator {
+        let mut oscillator = UnisonOscillator::new(SAMPLE_RATE, WaveTable::from_waveform(WaveForm::Sine, 1024).unwrap())
+            .with_voices(voices)
+            .with_detune(30.0);
+        oscillator.set_frequency(220.0);
+        oscillator.activate();
+        oscillator
+    }
+
+    fn process(oscillator: &mut UnisonOscillator, length: usize) -> (Vec<f32>, Vec<f32>) {
+        let (mut left, mut right) = (vec![0.0; length], vec![0.0; length]);
+        let context = ProcessContext { sample_rate: SAMPLE_RATE, time: 0 };
+        oscillator.process(&[], &mut [&mut left, &mut right], &context);
+        (left, right)
+    }
+
+    fn frequencies(oscillator: &UnisonOscillator) -> Vec<f64> {
+        oscillator.voices[..oscillator.get_voices()].iter()
+            .map(|voice| voice.phase.get_increment() * SAMPLE_RATE as f64)
+            .collect()
+    }
+
+    #[test]
+    fn one_voice_is_a_plain_oscillator() {
+        let wavetable = WaveTable::from_waveform(WaveForm::Saw, 1024).unwrap();
+        let mut unison = UnisonOscillator::new(SAMPLE_RATE, wavetable.clone()).with_voice_phase(VoicePhase::Fixed(0.0));
+        let mut plain = WaveTableOscillator::new(SAMPLE_RATE, wavetable);
+        unison.set_frequency(330.0);
+        plain.set_frequency(330.0);
+        unison.activate();
+        plain.activate();
+
+        let (left, right) = process(&mut unison, 256);
+        for (i, (left, right)) in left.iter().zip(right.iter()).enumerate() {
+            let expected = plain.get_sample();
+            assert!(left == right && (left - expected).abs() < 1e-6, "{left} and {expected} at {i}");
+        }
+    }
+
+    #[test]
+    fn voices_follow_the_detune_curve() {
+        let mut oscillator = unison(3).with_detune(100.0);
+        process(&mut oscillator, 1);
+        let expected = [220.0 * 2f64.powf(-50.0 / 1200.0), 220.0, 220.0 * 2f64.powf(50.0 / 1200.0)];
+        for (frequency, expected) in frequencies(&oscillator).iter().zip(expected) {
+            assert!((frequency - expected).abs() < 1e-6, "{frequency} instead of {expected}");
+        }
+
+        // the inner voices of 5 sit at a quarter, half and three quarters of the outer detune
+        for (curve, inner) in [(DetuneCurve::Linear, 0.5), (DetuneCurve::Center, 0.25), (DetuneCurve::Edges, 0.5f64.sqrt())] {
+            let mut oscillator = unison(5).with_detune(100.0).with_detune_curve(curve);
+            process(&mut oscillator, 1);
+            let cents: Vec<f64> = frequencies(&oscillator).iter().map(|frequency| 1200.0 * (frequency / 220.0).log2()).collect();
+            assert!((cents[4] - 50.0).abs() < 1e-6 && (cents[3] - 50.0 * inner).abs() < 1e-6, "{curve:?}: {cents:?}");
+            assert!((cents[0] + cents[4]).abs() < 1e-6 && cents[2].abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn width_and_blend() {
+        // two voices panned hard apart, each one alone on its side
+        let mut oscillator = unison(2).with_voice_phase(VoicePhase::Fixed(0.25));
+        oscillator.set_width(1.0);
+        let (left, right) = process(&mut oscillator, 4800);
+        let (low, high) = (frequencies(&oscillator)[0], frequencies(&oscillator)[1]);
+        let gain = 0.5f32.sqrt();
+        for (i, (left, right)) in left.iter().zip(right.iter()).enumerate() {
+            let time = i as f64 / SAMPLE_RATE as f64;
+            let expected = |frequency: f64| gain * (std::f64::consts::TAU * (0.25 + frequency * time)).sin() as f32;
+            assert!((left - expected(low)).abs() < 1e-3 && (right - expected(high)).abs() < 1e-3, "at {i}");
+        }
+
+        // once the panning has faded to the centre
+        oscillator.set_width(0.0);
+        let (left, right) = process(&mut oscillator, 480);
+        assert_eq!(left[240..], right[240..]);
+
+        // no blend leaves only the centre voice of 3
+        let mut oscillator = unison(3).with_voice_phase(VoicePhase::Fixed(0.0));
+        oscillator.set_blend(0.0);
+        let (left, _) = process(&mut oscillator, 480);
+        for (i, sample) in left.iter().enumerate() {
+            let expected = (std::f64::consts::TAU * 220.0 * i as f64 / SAMPLE_RATE as f64).sin() as f32;
+            assert!((sample - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn initial_phases() {
+        let mut random = unison(MAX_VOICES);
+        process(&mut random, 1);
+        let phases: Vec<f64> = random.voices.iter().map(|voice| voice.phase.get_phase()).collect();
+        assert!(phases.windows(2).all(|pair| (pair[0] - pair[1]).abs() > 1e-6));
+
+        let mut fixed = unison(MAX_VOICES).with_voice_phase(VoicePhase::Fixed(0.5));
+        process(&mut fixed, 1);
+        let first = fixed.voices[0].phase.get_phase();
+        // they start together and drift apart by their detune
+        for voice in fixed.voices.iter() {
+            assert!((voice.phase.get_phase() - first).abs() < 1e-3);
+        }
+        fixed.reset_phases();
+        assert!(fixed.voices.iter().all(|voice| voice.phase.get_phase() == 0.5));
+    }
+
+    #[test]
+    fn voice_count_changes_are_click_free() {
+        let mut oscillator = unison(1).with_detune(50.0);
+        let voices = oscillator.voices_handle();
+        let mut output = process(&mut oscillator, 480).0;
+        voices.set(16.0);
+        output.extend(process(&mut oscillator, 960).0);
+        voices.set(3.0);
+        output.extend(process(&mut oscillator, 960).0);
+
+        // about as steep as the sum of the voices can get at 220 Hz, far from a jump
+        let max_step = output.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.15, "jumped by {max_step}");
+        assert_eq!(oscillator.voices.iter().filter(|voice| !voice.is_silent()).count(), 3);
+        assert_eq!(oscillator.get_voices(), 3);
+
+        oscillator.set_voices(100);
+        assert_eq!(oscillator.get_voices(), MAX_VOICES);
+    }
+}
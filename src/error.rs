@@ -5,11 +5,13 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 pub use crate::audio::error::AudioError;
 pub use crate::gui::error::GuiError;
+pub use crate::synthesis::error::SynthesisError;
 
 #[derive(Debug, Clone, AsRefStr)]
 pub enum Error {
     Audio(AudioError),
     Gui(GuiError),
+    Synthesis(SynthesisError),
 }
 
 impl std::error::Error for Error {}
@@ -1,8 +1,6 @@
-use std::sync::{Arc, Mutex};
-
 use iced::widget::{column, component, container, text, Component};
 use iced::Element;
-use crate::audio::graph::AudioDevice;
+use crate::audio::{graph::{AudioGraph, NodeId}, parameter::ParameterHandle};
 use crate::gui::widgets::core::normal::Normal;
 use crate::gui::widgets::core::normal_param::NormalParam;
 use crate::gui::widgets::core::range::LogDBRange;
@@ -17,22 +15,23 @@ pub enum AmplifierUIEvent {
 
 #[derive(Clone, Debug)]
 pub struct AmplifierUI {
-    amplifier: Arc<Mutex<Box<dyn AudioDevice>>>, //should be Amplifier, not dyn audiodevice but somehow that's impossible ;w;
+    amplitude: ParameterHandle,
 
     gain_range: LogDBRange,
     gain_param: NormalParam,
 }
 
 impl AmplifierUI {
-    pub fn new(amplifier: Arc<Mutex<Box<Amplifier>>>) -> Self {
-        let guard = amplifier.lock().unwrap();
-        let gain = guard.get_amplitude();
-        drop(guard);
+    /// `amplifier` has to be the id of an [`Amplifier`] node in `graph`
+    pub fn new(graph: &AudioGraph, amplifier: NodeId) -> Self {
+        let amplifier = graph.get_device::<Amplifier>(amplifier)
+            .expect("amplifier ui needs an amplifier node");
+        let gain = amplifier.get_amplitude();
 
         let gain_range = LogDBRange::new(-56.0, 0.0, Normal::from_clipped(0.7));
 
         Self {
-            amplifier,
+            amplitude: amplifier.amplitude_handle(),
 
             gain_range,
             gain_param: gain_range.normal_param(gain, -20.0),
@@ -58,11 +57,7 @@ impl<Message> Component<Message> for AmplifierUI
             AmplifierUIEvent::GainChanged(normal) => {
                 self.gain_param.update(normal);
                 let new_gain = self.gain_range.unmap_to_value(normal);
-                
-                self.amplifier
-                    .lock()
-                    .unwrap()
-                    .set_amplitude(decibel_to_amplitude(new_gain));
+                self.amplitude.set(decibel_to_amplitude(new_gain));
             }
         }
         None
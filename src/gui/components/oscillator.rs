@@ -2,8 +2,8 @@ use std::sync::{Arc, Mutex};
 
 use iced::{widget::{column, row, container, pick_list, text, Component, component}, Element};
 
-use crate::{gui::widgets::{core::{normal::Normal, normal_param::NormalParam, range::FreqRange}, knob::Knob}, synthesis::wavetable::WaveTable};
-use crate::{devices::oscillator::WaveTableOscillator, synthesis::waveforms::WaveForm};
+use crate::{audio::{command::GraphController, graph::{AudioGraph, NodeId}, parameter::ParameterHandle}, gui::widgets::{core::{normal::Normal, normal_param::NormalParam, range::FreqRange}, knob::Knob}, synthesis::wavetable::{WaveTable, DEFAULT_TABLE_LENGTH}};
+use crate::{devices::oscillator::{SetWavetable, WaveTableOscillator}, synthesis::waveforms::WaveForm, Error};
 
 
 #[derive(Clone, Copy, Debug)]
@@ -14,7 +14,9 @@ pub enum OscillatorUIEvent {
 
 #[derive(Clone, Debug)]
 pub struct OscillatorUI {
-    oscillator: Arc<Mutex<Box<WaveTableOscillator>>>,
+    controller: Arc<Mutex<GraphController>>,
+    oscillator: NodeId,
+    frequency: ParameterHandle,
 
     pitch_range: FreqRange,
     pitch_param: NormalParam,
@@ -23,16 +25,19 @@ pub struct OscillatorUI {
 }
 
 impl OscillatorUI {
-    pub fn new(oscillator: Arc<Mutex<Box<WaveTableOscillator>>>) -> Self {
-        let guard = oscillator.lock().unwrap();
-        let frequency = guard.get_frequency();
-        let waveform = guard.get_waveform();
-        drop(guard);
+    /// `oscillator` has to be the id of a [`WaveTableOscillator`] node in `graph`
+    pub fn new(controller: Arc<Mutex<GraphController>>, graph: &AudioGraph, oscillator: NodeId) -> Self {
+        let device = graph.get_device::<WaveTableOscillator>(oscillator)
+            .expect("oscillator ui needs an oscillator node");
+        let frequency = device.get_frequency();
+        let waveform = device.get_waveform();
 
         let pitch_range = FreqRange::new(20.0, 20000.0);
 
         Self {
+            controller,
             oscillator,
+            frequency: device.frequency_handle(),
             pitch_range,
             pitch_param: pitch_range.normal_param(frequency, 220.0),
             waveform,
@@ -45,7 +50,8 @@ Renderer: iced_native::text::Renderer + 'static,
 Renderer::Theme: knob::Catalog + widget::text::Catalog,
     */
 
-impl<Message> Component<Message> for OscillatorUI
+/// Errors from changing the oscillator are passed up as a `Message` built from them
+impl<Message: From<Vec<Error>>> Component<Message> for OscillatorUI
     /*  iced_audio::knob::Catalog +
         iced::widget::text::Catalog +
         iced::widget::container::Catalog + 
@@ -65,12 +71,23 @@ impl<Message> Component<Message> for OscillatorUI
             OscillatorUIEvent::PitchChanged(normal) => {
                 self.pitch_param.update(normal);
                 let new_pitch = self.pitch_range.unmap_to_value(normal);
-                self.oscillator.lock().unwrap().set_frequency(new_pitch);
+                self.frequency.set(new_pitch);
             },
             OscillatorUIEvent::WaveFormSelected(waveform) => {
-                println!("{waveform:?}");
-                let wavetable = WaveTable::from_waveform(waveform, 128);
-                self.oscillator.lock().unwrap().set_wavetable(wavetable);
+                let wavetable = match WaveTable::from_waveform(waveform, DEFAULT_TABLE_LENGTH) {
+                    Ok(wavetable) => wavetable,
+                    Err(err) => return Some(Message::from(vec![err])),
+                };
+                self.waveform = waveform;
+                let mut controller = self.controller.lock().unwrap();
+                // errors of earlier edits come back with the collected ones
+                let mut errors = controller.collect();
+                if let Err(err) = controller.edit(self.oscillator, SetWavetable::new(wavetable)) {
+                    errors.push(err);
+                }
+                if !errors.is_empty() {
+                    return Some(Message::from(errors));
+                }
             },
         }
         None
@@ -113,7 +130,7 @@ impl<Message> Component<Message> for OscillatorUI
     }
 }
 
-impl<'a, Message: 'a> From<OscillatorUI> for Element<'a, Message> {
+impl<'a, Message: From<Vec<Error>> + 'a> From<OscillatorUI> for Element<'a, Message> {
     fn from(oscillator_ui: OscillatorUI) -> Self {
         component(oscillator_ui)
     }
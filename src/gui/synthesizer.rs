@@ -1,31 +1,50 @@
 use std::sync::{Arc, Mutex};
 
 use iced::Element;
-use iced::widget::{row, column};
-use crate::devices::{amplifier::Amplifier, oscillator::WaveTableOscillator};
+use iced::widget::{row, column, text};
+use crate::{audio::{command::GraphController, graph::{AudioGraph, NodeId}}, Error};
 
 use super::components::{amplifier::AmplifierUI, oscillator::OscillatorUI};
 
 
 #[derive(Clone, Debug)]
-pub enum Message {}
+pub enum Message {
+    /// Something the components couldn't do, shown until the next one
+    Errors(Vec<Error>),
+}
+
+impl From<Vec<Error>> for Message {
+    fn from(errors: Vec<Error>) -> Self {
+        Self::Errors(errors)
+    }
+}
 
 
 pub struct SynthesizerUI {
     amplifier_ui: AmplifierUI,
     oscillators_ui: Vec<OscillatorUI>,
+    errors: Vec<Error>, //reported last by a component
 }
 
 impl SynthesizerUI {
-    pub fn new(amplifier: Arc<Mutex<Box<Amplifier>>>, oscillators: Vec<Arc<Mutex<Box<WaveTableOscillator>>>>) -> Self {
+    /// `graph` is the graph `controller` edits, only read for the starting values of the components
+    pub fn new(controller: GraphController, graph: &AudioGraph, amplifier: NodeId, oscillators: Vec<NodeId>) -> Self {
+        let controller = Arc::new(Mutex::new(controller));
         let mut oscillators_ui = Vec::with_capacity(oscillators.len());
         for oscillator in oscillators {
-            oscillators_ui.push(OscillatorUI::new(oscillator));
+            oscillators_ui.push(OscillatorUI::new(controller.clone(), graph, oscillator));
         }
 
         Self {
-            amplifier_ui: AmplifierUI::new(amplifier),
+            amplifier_ui: AmplifierUI::new(graph, amplifier),
             oscillators_ui,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::Errors(errors) => self.errors = errors,
         }
     }
 
@@ -33,10 +52,13 @@ impl SynthesizerUI {
         let oscillators_ui: Vec<Element<Message>> = 
             self.oscillators_ui.iter()
                 .map(|ui| ui.to_owned().into()).collect();
-        row![
-            self.amplifier_ui.clone(),
-            column(oscillators_ui),
-
+        let errors = self.errors.iter().map(|err| text(err.to_string()).into());
+        column![
+            row![
+                self.amplifier_ui.clone(),
+                column(oscillators_ui),
+            ],
+            column(errors),
         ].into()
     }
 }
\ No newline at end of file
@@ -1,70 +1,40 @@
-use std::{error::Error, sync::{Arc, Mutex}};
+use std::error::Error;
 
-use iced::Settings;
-use rodio::OutputStream;
-use wavetable_synthesizer::{audio::graph::{AudioDevice, AudioGraph, AudioNode}, devices::{amplifier::Amplifier, oscillator::{self, WaveTableOscillator}}, gui::synthesizer::SynthesizerUI, synthesis::{waveforms::WaveForm, wavetable::WaveTable}};
+use iced::Task;
+use wavetable_synthesizer::{audio::{backend::{AudioBackend, CpalBackend}, command::command_queue, graph::{AudioGraph, MASTER_NODE_ID}, AudioThread}, devices::{amplifier::Amplifier, oscillator::WaveTableOscillator}, gui::synthesizer::SynthesizerUI, synthesis::{waveforms::WaveForm, wavetable::{WaveTable, DEFAULT_TABLE_LENGTH}}};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let sample_rate = Arc::new(48000);
+    let backend = CpalBackend::new()?;
+    let sample_rate = backend.sample_rate();
     let base_frequency = 50.0;
 
-    let wavetable = WaveTable::from_fn(WaveForm::Square.get_fn(), 128);
+    let wavetable = WaveTable::from_waveform(WaveForm::Square, DEFAULT_TABLE_LENGTH)?;
 
     let oscillator_blueprint = WaveTableOscillator::new(sample_rate, wavetable);
 
-    let mut oscillators: Vec<Arc<Mutex<Box<dyn AudioDevice>>>> = Vec::new();
+    let mut graph = AudioGraph::new(sample_rate);
+    let amplifier = graph.add_node(Amplifier::new(0.7));
+    graph.connect(amplifier, MASTER_NODE_ID)?;
+
+    let mut oscillators = Vec::new();
     for i in 1..=1 {
         let mut osc = oscillator_blueprint.clone();
         osc.set_frequency(base_frequency*(i as f32));
-        oscillators.push(Arc::new(Mutex::new(Box::new(osc))));
+        osc.activate();
+        let osc = graph.add_node(osc);
+        graph.connect(osc, amplifier)?;
+        oscillators.push(osc);
     }
 
-    let mut amplifier = Arc::new(Mutex::new(Box::new(
-        Amplifier::new(0.7)
-    )));
-
-    
-    let oscillator_nodes = oscillators.iter()
-        .map(|osc| {
-            let osc = osc.clone(); 
-            AudioNode::new(osc.clone(), Vec::new())
-        })
-        .collect();
-
-    let amplifier_node = AudioNode::new(amplifier.clone(), oscillator_nodes);
-
-    let audio_graph = AudioGraph::new(
-        sample_rate,
-
-    );
-
-    let synth = Arc::new(Mutex::new(
-        SynthesizerUI::new(
-            amplifier,
-            oscillators,
-        )
-    ));
-
-    let audio_source = AudioSource::new(synth.clone());
+    let (controller, processor) = command_queue(graph, 64);
+    let synthesizer_ui = SynthesizerUI::new(controller, processor.get_graph(), amplifier, oscillators);
 
-    let (_stream, stream_handle) = OutputStream::try_default()?;
+    let mut audio_thread = AudioThread::with_backend(processor, backend);
+    audio_thread.start()?;
 
+    iced::application("Wavetable Synthesizer", SynthesizerUI::update, SynthesizerUI::view)
+        .run_with(move || (synthesizer_ui, Task::none()))?;
 
-    let _result = stream_handle.play_raw(audio_source.convert_samples());
-
-    let ui_settings = Settings::with_flags(
-        Flags::new(synth)
-    );
-
-    SynthesizerUI::run(ui_settings)?;
-
+    audio_thread.stop()?;
     Ok(())
-
-    //idk
-    /*
-    loop {
-        std::thread::sleep(Duration::from_millis(100));
-    */
 }
-
-
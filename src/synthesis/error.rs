@@ -0,0 +1,27 @@
+use std::fmt::Display;
+
+use strum_macros::AsRefStr;
+
+#[derive(Debug, Clone, AsRefStr)]
+pub enum SynthesisError {
+    SynthesisEmptyWavetable,
+    SynthesisInvalidClmChunk(String),
+    SynthesisInvalidFrameSize(usize, usize),
+    SynthesisWaveFormHasNoFunction,
+    SynthesisExpressionUnexpectedCharacter(usize, char),
+    SynthesisExpressionInvalidNumber(usize, String),
+    SynthesisExpressionUnexpectedToken(usize, String),
+    SynthesisExpressionUnexpectedEnd(usize),
+    SynthesisExpressionUnknownVariable(usize, String),
+    SynthesisExpressionUnknownFunction(usize, String),
+    SynthesisExpressionWrongArgumentCount(usize, String, usize, usize),
+    SynthesisExpressionTooDeep(usize),
+}
+
+impl std::error::Error for SynthesisError {}
+
+impl Display for SynthesisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
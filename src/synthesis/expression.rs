@@ -0,0 +1,559 @@
+use std::{f32::consts::{E, PI, TAU}, fmt::Display, str::FromStr};
+
+use crate::{error::SynthesisError, Error, Result};
+
+use super::wavetable::WaveTable;
+
+/// Deepest stack an expression may need, deeper ones fail to parse
+const MAX_STACK: usize = 64;
+
+/// Deepest the parentheses, function calls and prefix operators of an expression may nest,
+/// so hostile input fails to parse instead of overflowing the parser's stack
+const MAX_NESTING: usize = 128;
+
+/// A waveform written as a math expression, like `sin(x) + 0.5 * sin(2 * x)`, compiled into a small stack program.
+///
+/// Variables are `x` (the phase in radians, like the argument of [`WaveTable::from_fn`]), `phase` (in cycles, [0, 1))
+/// and `frame` (the position of the frame in [0, 1]), constants are `pi`, `tau` and `e`.
+/// Operators are `+ - * / % ^`, comparisons and `&& || !`, which treat anything but 0 as true and return 1 or 0.
+/// Functions are `sin cos tan asin acos atan sinh cosh tanh exp ln log2 log10 sqrt abs sign floor ceil round fract noise`,
+/// `min max pow atan2 mod step` and `clamp lerp if`, piecewise shapes are made with `if(condition, then, else)`
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    program: Vec<Op>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Constant(f32),
+    Radians,
+    Phase,
+    Frame,
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+    Ternary(fn(f32, f32, f32) -> f32),
+}
+
+impl Op {
+    /// Values the op takes off the stack
+    fn arity(&self) -> usize {
+        match self {
+            Self::Constant(_) | Self::Radians | Self::Phase | Self::Frame => 0,
+            Self::Unary(_) => 1,
+            Self::Binary(_) => 2,
+            Self::Ternary(_) => 3,
+        }
+    }
+}
+
+fn truth(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// White noise in [-1, 1] that is the same for the same input, so tables come out the same every time
+fn noise(value: f32) -> f32 {
+    let mut hash = value.to_bits().wrapping_mul(0x9e37_79b9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+fn function(name: &str) -> Option<Op> {
+    Some(match name {
+        "sin" => Op::Unary(f32::sin),
+        "cos" => Op::Unary(f32::cos),
+        "tan" => Op::Unary(f32::tan),
+        "asin" => Op::Unary(f32::asin),
+        "acos" => Op::Unary(f32::acos),
+        "atan" => Op::Unary(f32::atan),
+        "sinh" => Op::Unary(f32::sinh),
+        "cosh" => Op::Unary(f32::cosh),
+        "tanh" => Op::Unary(f32::tanh),
+        "exp" => Op::Unary(f32::exp),
+        "ln" => Op::Unary(f32::ln),
+        "log2" => Op::Unary(f32::log2),
+        "log10" => Op::Unary(f32::log10),
+        "sqrt" => Op::Unary(f32::sqrt),
+        "abs" => Op::Unary(f32::abs),
+        "sign" => Op::Unary(|value| if value == 0.0 { 0.0 } else { value.signum() }),
+        "floor" => Op::Unary(f32::floor),
+        "ceil" => Op::Unary(f32::ceil),
+        "round" => Op::Unary(f32::round),
+        "fract" => Op::Unary(|value| value - value.floor()),
+        "noise" => Op::Unary(noise),
+        "min" => Op::Binary(f32::min),
+        "max" => Op::Binary(f32::max),
+        "pow" => Op::Binary(f32::powf),
+        "atan2" => Op::Binary(f32::atan2),
+        "mod" => Op::Binary(f32::rem_euclid),
+        "step" => Op::Binary(|edge, value| truth(value >= edge)),
+        "clamp" => Op::Ternary(|value, min, max| value.max(min).min(max)),
+        "lerp" => Op::Ternary(|a, b, t| a + t * (b - a)),
+        "if" => Op::Ternary(|condition, then, otherwise| if condition != 0.0 { then } else { otherwise }),
+        _ => return None,
+    })
+}
+
+fn variable(name: &str) -> Option<Op> {
+    Some(match name {
+        "x" => Op::Radians,
+        "phase" => Op::Phase,
+        "frame" => Op::Frame,
+        "pi" => Op::Constant(PI),
+        "tau" => Op::Constant(TAU),
+        "e" => Op::Constant(E),
+        _ => return None,
+    })
+}
+
+fn binary_operator(symbol: &str) -> Option<fn(f32, f32) -> f32> {
+    Some(match symbol {
+        "+" => |a, b| a + b,
+        "-" => |a, b| a - b,
+        "*" => |a, b| a * b,
+        "/" => |a, b| a / b,
+        "%" => f32::rem_euclid,
+        "^" => f32::powf,
+        "<" => |a, b| truth(a < b),
+        "<=" => |a, b| truth(a <= b),
+        ">" => |a, b| truth(a > b),
+        ">=" => |a, b| truth(a >= b),
+        "==" => |a, b| truth(a == b),
+        "!=" => |a, b| truth(a != b),
+        "&&" => |a, b| truth(a != 0.0 && b != 0.0),
+        "||" => |a, b| truth(a != 0.0 || b != 0.0),
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Number(f32),
+    Name(String),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    position: usize, //byte offset into the source
+}
+
+/// Longer symbols first, so `<=` isn't read as `<`
+const SYMBOLS: [&str; 18] = ["<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "(", ")", ","];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(position, char)) = chars.peek() {
+        if char.is_whitespace() {
+            chars.next();
+        } else if char.is_ascii_digit() || char == '.' {
+            let mut end = position;
+            let mut previous = ' ';
+            // exponents like 1e-3 take a sign
+            while let Some(&(index, char)) = chars.peek() {
+                let exponent_sign = (char == '-' || char == '+') && (previous == 'e' || previous == 'E');
+                if !(char.is_ascii_alphanumeric() || char == '.' || exponent_sign) {
+                    break;
+                }
+                previous = char;
+                end = index + char.len_utf8();
+                chars.next();
+            }
+            let text = &source[position..end];
+            let number = text.parse().map_err(|_| Error::Synthesis(SynthesisError::SynthesisExpressionInvalidNumber(position, text.to_string())))?;
+            tokens.push(Token { kind: TokenKind::Number(number), position });
+        } else if char.is_alphabetic() || char == '_' {
+            let mut end = position;
+            while let Some(&(index, char)) = chars.peek() {
+                if !(char.is_alphanumeric() || char == '_') {
+                    break;
+                }
+                end = index + char.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token { kind: TokenKind::Name(source[position..end].to_string()), position });
+        } else {
+            let symbol = SYMBOLS.into_iter()
+                .find(|symbol| source[position..].starts_with(symbol))
+                .ok_or(Error::Synthesis(SynthesisError::SynthesisExpressionUnexpectedCharacter(position, char)))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Token { kind: TokenKind::Symbol(symbol), position });
+        }
+    }
+    tokens.push(Token { kind: TokenKind::End, position: source.len() });
+    Ok(tokens)
+}
+
+/// Recursive descent parser writing the program in postfix order as it goes
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    program: Vec<Op>,
+    depth: usize, //values on the stack after the program so far
+    max_depth: usize,
+    nesting: usize, //parentheses, calls and prefix operators the parser is currently inside of
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].clone();
+        if token.kind != TokenKind::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn unexpected(token: &Token) -> Error {
+        Error::Synthesis(match &token.kind {
+            TokenKind::End => SynthesisError::SynthesisExpressionUnexpectedEnd(token.position),
+            TokenKind::Number(number) => SynthesisError::SynthesisExpressionUnexpectedToken(token.position, number.to_string()),
+            TokenKind::Name(name) => SynthesisError::SynthesisExpressionUnexpectedToken(token.position, name.clone()),
+            TokenKind::Symbol(symbol) => SynthesisError::SynthesisExpressionUnexpectedToken(token.position, symbol.to_string()),
+        })
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Symbol(found) if found == symbol => Ok(()),
+            _ => Err(Self::unexpected(&token)),
+        }
+    }
+
+    /// Takes the next token if it's one of `symbols`
+    fn accept(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek().kind {
+            TokenKind::Symbol(symbol) if symbols.contains(&symbol) => {
+                self.next += 1;
+                Some(symbol)
+            },
+            _ => None,
+        }
+    }
+
+    /// Appends `op`, folding it into a constant right away if all of its inputs are constants
+    fn emit(&mut self, op: Op, position: usize) -> Result<()> {
+        let arity = op.arity();
+        let start = self.program.len() - arity;
+        let mut inputs = [0.0; 3];
+        let constant = self.program[start..].iter().zip(inputs.iter_mut()).all(|(input, value)| match input {
+            Op::Constant(constant) => {
+                *value = *constant;
+                true
+            },
+            _ => false,
+        });
+        let op = match op {
+            Op::Unary(function) if constant => Op::Constant(function(inputs[0])),
+            Op::Binary(function) if constant => Op::Constant(function(inputs[0], inputs[1])),
+            Op::Ternary(function) if constant => Op::Constant(function(inputs[0], inputs[1], inputs[2])),
+            op => op,
+        };
+        if matches!(op, Op::Constant(_)) && arity > 0 {
+            self.program.truncate(start);
+        }
+        self.program.push(op);
+
+        self.depth = self.depth + 1 - arity;
+        self.max_depth = self.max_depth.max(self.depth);
+        if self.max_depth > MAX_STACK {
+            return Err(Error::Synthesis(SynthesisError::SynthesisExpressionTooDeep(position)));
+        }
+        Ok(())
+    }
+
+    /// Parses operands with `operand` separated by any of `symbols`, all left associative
+    fn binary(&mut self, symbols: &[&'static str], operand: fn(&mut Self) -> Result<()>) -> Result<()> {
+        operand(self)?;
+        loop {
+            let position = self.peek().position;
+            let Some(symbol) = self.accept(symbols) else {
+                return Ok(());
+            };
+            operand(self)?;
+            self.emit(Op::Binary(binary_operator(symbol).expect("operator is listed")), position)?;
+        }
+    }
+
+    fn expression(&mut self) -> Result<()> {
+        self.binary(&["||"], |parser| parser.binary(&["&&"], Self::comparison))
+    }
+
+    fn comparison(&mut self) -> Result<()> {
+        self.binary(&["<", "<=", ">", ">=", "==", "!="], |parser| parser.binary(&["+", "-"], |parser| parser.binary(&["*", "/", "%"], Self::unary)))
+    }
+
+    /// Runs `parse` one level deeper, failing once the nesting passes [`MAX_NESTING`]
+    fn nested(&mut self, position: usize, parse: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        if self.nesting >= MAX_NESTING {
+            return Err(Error::Synthesis(SynthesisError::SynthesisExpressionTooDeep(position)));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn unary(&mut self) -> Result<()> {
+        let position = self.peek().position;
+        self.nested(position, Self::prefixed)
+    }
+
+    fn prefixed(&mut self) -> Result<()> {
+        let position = self.peek().position;
+        match self.accept(&["-", "!"]) {
+            Some("-") => {
+                self.unary()?;
+                self.emit(Op::Unary(|value| -value), position)
+            },
+            Some(_) => {
+                self.unary()?;
+                self.emit(Op::Unary(|value| truth(value == 0.0)), position)
+            },
+            None => self.power(),
+        }
+    }
+
+    /// Right associative, and binds tighter than a leading minus, so `-2^2` is -4
+    fn power(&mut self) -> Result<()> {
+        self.primary()?;
+        let position = self.peek().position;
+        if self.accept(&["^"]).is_some() {
+            self.unary()?;
+            self.emit(Op::Binary(f32::powf), position)?;
+        }
+        Ok(())
+    }
+
+    fn primary(&mut self) -> Result<()> {
+        let position = self.peek().position;
+        self.nested(position, Self::operand)
+    }
+
+    fn operand(&mut self) -> Result<()> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(number) => self.emit(Op::Constant(number), token.position),
+            TokenKind::Symbol("(") => {
+                self.expression()?;
+                self.expect(")")
+            },
+            TokenKind::Name(name) if self.accept(&["("]).is_some() => {
+                let op = function(&name)
+                    .ok_or(Error::Synthesis(SynthesisError::SynthesisExpressionUnknownFunction(token.position, name.clone())))?;
+                let mut arguments = 0;
+                if self.accept(&[")"]).is_none() {
+                    loop {
+                        self.expression()?;
+                        arguments += 1;
+                        if self.accept(&[","]).is_none() {
+                            break;
+                        }
+                    }
+                    self.expect(")")?;
+                }
+                if arguments != op.arity() {
+                    return Err(Error::Synthesis(SynthesisError::SynthesisExpressionWrongArgumentCount(token.position, name, op.arity(), arguments)));
+                }
+                self.emit(op, token.position)
+            },
+            TokenKind::Name(name) => {
+                let op = variable(&name)
+                    .ok_or(Error::Synthesis(SynthesisError::SynthesisExpressionUnknownVariable(token.position, name)))?;
+                self.emit(op, token.position)
+            },
+            _ => Err(Self::unexpected(&token)),
+        }
+    }
+}
+
+impl Expression {
+    /// Compiles `source`, errors carry the byte offset of the problem
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+            program: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+            nesting: 0,
+        };
+        parser.expression()?;
+        let token = parser.advance();
+        if token.kind != TokenKind::End {
+            return Err(Parser::unexpected(&token));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            program: parser.program,
+        })
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    /// The value at `x` radians into the cycle, in a frame at `frame` in [0, 1]
+    pub fn evaluate(&self, x: f32, frame: f32) -> f32 {
+        let mut stack = [0.0; MAX_STACK];
+        let mut depth = 0;
+        for op in self.program.iter() {
+            let value = match *op {
+                Op::Constant(value) => value,
+                Op::Radians => x,
+                Op::Phase => x / TAU,
+                Op::Frame => frame,
+                Op::Unary(function) => function(stack[depth - 1]),
+                Op::Binary(function) => function(stack[depth - 2], stack[depth - 1]),
+                Op::Ternary(function) => function(stack[depth - 3], stack[depth - 2], stack[depth - 1]),
+            };
+            depth -= op.arity();
+            stack[depth] = value;
+            depth += 1;
+        }
+        stack[0]
+    }
+
+    /// A table of `frame_count` frames, `frame` goes from 0 in the first to 1 in the last
+    pub fn to_wavetable(&self, length: usize, frame_count: usize) -> WaveTable {
+        WaveTable::from_frames_fn(|frame, x| self.evaluate(x, frame), length, frame_count)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        Self::parse(source)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use crate::{error::SynthesisError, synthesis::{waveforms::WaveForm, wavetable::WaveTable}, Error};
+    use super::Expression;
+
+    fn evaluate(source: &str) -> f32 {
+        Expression::parse(source).unwrap().evaluate(0.0, 0.0)
+    }
+
+    fn error(source: &str) -> SynthesisError {
+        match Expression::parse(source) {
+            Err(Error::Synthesis(error)) => error,
+            other => panic!("{source} should fail to parse, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3 ^ 2"), 19.0);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9.0);
+        assert_eq!(evaluate("-2^2"), -4.0);
+        assert_eq!(evaluate("2^3^2"), 512.0);
+        assert_eq!(evaluate("8 / 4 / 2 - 1 - 1"), -1.0);
+        assert_eq!(evaluate("-7 % 4"), 1.0);
+        assert_eq!(evaluate("1 < 2 && 3 >= 3 || 0"), 1.0);
+        assert_eq!(evaluate("!(1 == 1) + (2 != 2)"), 0.0);
+        assert!((evaluate("1.5e1 + .5 + 2E-1") - 15.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        let expression = Expression::parse("sin(x) * frame + phase").unwrap();
+        assert!((expression.evaluate(PI / 2.0, 0.5) - 0.75).abs() < 1e-6);
+
+        assert_eq!(evaluate("clamp(5, -1, 1) + min(2, 3) + max(2, 3) + abs(-1)"), 7.0);
+        assert_eq!(evaluate("if(0, 1, 2) + if(3, 4, 5) + step(0.5, 1)"), 7.0);
+        assert_eq!(evaluate("lerp(1, 3, 0.25) + fract(2.75) + sign(-3) + floor(-0.5)"), 0.25);
+        assert!((evaluate("ln(e) + log2(8) + pow(2, 0.5) ^ 2 + tau / pi") - 8.0).abs() < 1e-5);
+
+        // the same input gives the same noise
+        let noise = Expression::parse("noise(x)").unwrap();
+        let values: Vec<f32> = (0..1000).map(|i| noise.evaluate(i as f32 * 0.01, 0.0)).collect();
+        assert!(values.iter().all(|value| (-1.0..=1.0).contains(value)));
+        assert_eq!(values[10], noise.evaluate(10.0 * 0.01, 0.0));
+        assert!(values.iter().sum::<f32>().abs() < 100.0 && values.iter().any(|value| *value > 0.5));
+    }
+
+    #[test]
+    fn matches_the_builtin_waveforms() {
+        let sine = Expression::parse("sin(x)").unwrap().to_wavetable(256, 1);
+        assert_eq!(sine.get_level(0), WaveTable::from_waveform(WaveForm::Sine, 256).unwrap().get_level(0));
+
+        // piecewise
+        let square = "if(x <= pi, 1, -1)".parse::<Expression>().unwrap().to_wavetable(256, 1);
+        assert_eq!(square.get_level(0), WaveTable::from_waveform(WaveForm::Square, 256).unwrap().get_level(0));
+
+        let morph = Expression::parse("lerp(sin(x), 2 * phase - 1, frame)").unwrap().to_wavetable(64, 3);
+        assert_eq!(morph.frame_count(), 3);
+        assert!((morph.get_frame_level(1, 0)[16] - 0.25).abs() < 0.05);
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        assert_eq!(Expression::parse("2 * pi * 0.5 + sqrt(4)").unwrap().program.len(), 1);
+        assert_eq!(Expression::parse("sin(2 * x)").unwrap().program.len(), 4);
+        assert_eq!(Expression::parse("sin(tau * phase)").unwrap().to_string(), "sin(tau * phase)");
+    }
+
+    #[test]
+    fn errors_carry_positions() {
+        assert!(matches!(error("1 + $"), SynthesisError::SynthesisExpressionUnexpectedCharacter(4, '$')));
+        assert!(matches!(error("sin(x"), SynthesisError::SynthesisExpressionUnexpectedEnd(5)));
+        assert!(matches!(error("1 +"), SynthesisError::SynthesisExpressionUnexpectedEnd(3)));
+        assert!(matches!(error("1 2"), SynthesisError::SynthesisExpressionUnexpectedToken(2, token) if token == "2"));
+        assert!(matches!(error("(1))"), SynthesisError::SynthesisExpressionUnexpectedToken(3, token) if token == ")"));
+        assert!(matches!(error("2 * y"), SynthesisError::SynthesisExpressionUnknownVariable(4, name) if name == "y"));
+        assert!(matches!(error("x + saw(x)"), SynthesisError::SynthesisExpressionUnknownFunction(4, name) if name == "saw"));
+        assert!(matches!(error("clamp(x, 1)"), SynthesisError::SynthesisExpressionWrongArgumentCount(0, name, 3, 2) if name == "clamp"));
+        assert!(matches!(error("1.2.3"), SynthesisError::SynthesisExpressionInvalidNumber(0, text) if text == "1.2.3"));
+
+        let deep = "x+(".repeat(100) + "x" + &")".repeat(100);
+        assert!(matches!(error(&deep), SynthesisError::SynthesisExpressionTooDeep(_)));
+        // wide but shallow is fine
+        assert!(Expression::parse(&["x"; 500].join(" + ")).is_ok());
+        assert!((Expression::parse(&["sin(x)"; 3].join(" + ")).unwrap().evaluate(TAU / 4.0, 0.0) - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        // far deeper than the parser could recurse
+        let parentheses = "(".repeat(2000) + "x" + &")".repeat(2000);
+        assert!(matches!(error(&parentheses), SynthesisError::SynthesisExpressionTooDeep(_)));
+        assert!(matches!(error(&("-".repeat(100_000) + "x")), SynthesisError::SynthesisExpressionTooDeep(_)));
+        assert!(matches!(error(&("!".repeat(2000) + "x")), SynthesisError::SynthesisExpressionTooDeep(_)));
+        assert!(matches!(error(&("sin(".repeat(2000) + "x" + &")".repeat(2000))), SynthesisError::SynthesisExpressionTooDeep(_)));
+
+        // a reasonable depth still parses
+        let shallow = "(".repeat(20) + "x" + &")".repeat(20);
+        assert_eq!(Expression::parse(&shallow).unwrap().evaluate(1.5, 0.0), 1.5);
+        assert_eq!(Expression::parse(&("-".repeat(20) + "x")).unwrap().evaluate(1.5, 0.0), 1.5);
+    }
+}
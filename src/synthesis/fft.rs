@@ -0,0 +1,227 @@
+use std::{f64::consts::TAU, ops::{Add, Mul, Sub}};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const ZERO: Self = Self::new(0.0, 0.0);
+
+    pub const fn new(re: f64, im: f64) -> Self {
+        Self {
+            re,
+            im,
+        }
+    }
+
+    /// `magnitude` at `angle` radians
+    pub fn from_polar(magnitude: f64, angle: f64) -> Self {
+        Self::new(magnitude * angle.cos(), magnitude * angle.sin())
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn phase(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(&self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn scale(&self, factor: f64) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+/// In place forward FFT, radix 2 for power of two lengths and Bluestein's algorithm for everything else, O(n log n) either way.
+/// Not meant for the audio thread, it's used for building and analysing tables
+pub fn fft(buffer: &mut [Complex]) {
+    transform(buffer, -1.0);
+}
+
+/// In place inverse of [`fft`], including the `1 / n` scaling
+pub fn ifft(buffer: &mut [Complex]) {
+    transform(buffer, 1.0);
+    let scale = 1.0 / buffer.len() as f64;
+    for value in buffer.iter_mut() {
+        *value = value.scale(scale);
+    }
+}
+
+fn transform(buffer: &mut [Complex], sign: f64) {
+    let length = buffer.len();
+    if length <= 1 {
+        return;
+    }
+    if length.is_power_of_two() {
+        radix2(buffer, sign);
+    } else {
+        bluestein(buffer, sign);
+    }
+}
+
+/// Unscaled transform of a power of two length buffer
+fn radix2(buffer: &mut [Complex], sign: f64) {
+    let length = buffer.len();
+
+    // bit reversal permutation
+    let bits = length.trailing_zeros();
+    for i in 0..length {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+    while size <= length {
+        let step = Complex::from_polar(1.0, sign * TAU / size as f64);
+        for start in (0..length).step_by(size) {
+            let mut twiddle = Complex::new(1.0, 0.0);
+            for i in 0..size / 2 {
+                let even = buffer[start + i];
+                let odd = buffer[start + i + size / 2] * twiddle;
+                buffer[start + i] = even + odd;
+                buffer[start + i + size / 2] = even - odd;
+                twiddle = twiddle * step;
+            }
+        }
+        size *= 2;
+    }
+}
+
+/// Unscaled transform of any length, written as a convolution with a chirp that is done with power of two transforms.
+/// Uses `k * n = (k² + n² - (k - n)²) / 2` to turn the DFT's twiddle factors into chirps
+fn bluestein(buffer: &mut [Complex], sign: f64) {
+    let length = buffer.len();
+    let padded = (2 * length - 1).next_power_of_two();
+
+    // k² is taken modulo 2n before converting, the chirp repeats with it and large angles would lose precision
+    let chirp: Vec<Complex> = (0..length)
+        .map(|k| Complex::from_polar(1.0, sign * TAU / 2.0 * ((k * k) % (2 * length)) as f64 / length as f64))
+        .collect();
+
+    let mut signal = vec![Complex::ZERO; padded];
+    for ((signal, value), chirp) in signal.iter_mut().zip(buffer.iter()).zip(chirp.iter()) {
+        *signal = *value * *chirp;
+    }
+    let mut kernel = vec![Complex::ZERO; padded];
+    kernel[0] = chirp[0].conj();
+    for k in 1..length {
+        kernel[k] = chirp[k].conj();
+        kernel[padded - k] = chirp[k].conj();
+    }
+
+    radix2(&mut signal, -1.0);
+    radix2(&mut kernel, -1.0);
+    for (signal, kernel) in signal.iter_mut().zip(kernel.iter()) {
+        *signal = *signal * *kernel;
+    }
+    radix2(&mut signal, 1.0);
+
+    let scale = 1.0 / padded as f64;
+    for ((value, convolved), chirp) in buffer.iter_mut().zip(signal.iter()).zip(chirp.iter()) {
+        *value = (*convolved * *chirp).scale(scale);
+    }
+}
+
+/// Spectrum of a real signal, all `samples.len()` bins
+pub fn real_fft(samples: &[f32]) -> Vec<Complex> {
+    let mut buffer: Vec<Complex> = samples.iter().map(|sample| Complex::new(*sample as f64, 0.0)).collect();
+    fft(&mut buffer);
+    buffer
+}
+
+/// Real part of the inverse of `spectrum`, which should be conjugate symmetric
+pub fn real_ifft(spectrum: &[Complex]) -> Vec<f32> {
+    let mut buffer = spectrum.to_vec();
+    ifft(&mut buffer);
+    buffer.into_iter().map(|value| value.re as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::{fft, ifft, real_fft, real_ifft, Complex};
+
+    #[test]
+    fn finds_sine_bins() {
+        for length in [64, 60] {
+            let samples: Vec<f32> = (0..length).map(|i| (TAU * 5.0 * i as f64 / length as f64).sin() as f32).collect();
+            let spectrum = real_fft(&samples);
+            for (bin, value) in spectrum.iter().enumerate() {
+                let expected = if bin == 5 || bin == length - 5 { length as f64 / 2.0 } else { 0.0 };
+                assert!((value.magnitude() - expected).abs() < 1e-3, "{length} samples, bin {bin}: {}", value.magnitude());
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for length in [1, 2, 16, 1024, 12] {
+            let input: Vec<Complex> = (0..length).map(|i| Complex::new((i * 7 % 5) as f64, (i % 3) as f64 - 1.0)).collect();
+            let mut buffer = input.clone();
+            fft(&mut buffer);
+            ifft(&mut buffer);
+            for (a, b) in input.iter().zip(buffer.iter()) {
+                assert!((*a - *b).magnitude() < 1e-9);
+            }
+        }
+
+        let samples = [0.5, -0.25, 1.0, 0.0];
+        assert!(real_ifft(&real_fft(&samples)).iter().zip(samples).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn any_length_matches_the_dft() {
+        for length in [3, 12, 60, 1000, 2047] {
+            let input: Vec<Complex> = (0..length).map(|i| Complex::new(((i * 7) % 5) as f64 - 2.0, ((i * 3) % 4) as f64 * 0.5)).collect();
+            let mut buffer = input.clone();
+            fft(&mut buffer);
+            for (k, value) in buffer.iter().enumerate() {
+                let expected = input.iter().enumerate().fold(Complex::ZERO, |sum, (n, sample)| {
+                    sum + *sample * Complex::from_polar(1.0, -TAU * ((k * n) % length) as f64 / length as f64)
+                });
+                assert!((*value - expected).magnitude() < 1e-6 * length as f64, "{length} samples, bin {k}");
+            }
+        }
+    }
+
+    #[test]
+    fn long_odd_lengths_are_fast() {
+        // a plain DFT of this would take billions of operations
+        let samples: Vec<f32> = (0..100_003).map(|i| (TAU * 440.0 * i as f64 / 44100.0).sin() as f32).collect();
+        let restored = real_ifft(&real_fft(&samples));
+        assert!(restored.iter().zip(&samples).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{f64::consts::PI, sync::OnceLock};
+
+/// Taps on each side of the read position for [`Interpolation::Sinc`]
+const SINC_HALF_TAPS: usize = 8;
+/// Fractional positions the sinc kernel is precomputed for, positions in between are interpolated
+const SINC_PHASES: usize = 512;
+
+/// How a [`WaveTable`](super::wavetable::WaveTable) reads between its samples.
+/// Ordered from cheapest to most accurate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The sample at or before the read position
+    None,
+    #[default]
+    Linear,
+    /// 4 point, 3rd order Hermite (Catmull-Rom)
+    CubicHermite,
+    /// 4 point, 3rd order Lagrange polynomial
+    Lagrange,
+    /// 16 point sinc with a Blackman window
+    Sinc,
+}
+
+impl Interpolation {
+    pub const ALL: [Self; 5] = [
+        Self::None,
+        Self::Linear,
+        Self::CubicHermite,
+        Self::Lagrange,
+        Self::Sinc,
+    ];
+
+    /// Reads the cyclic `table` at the fractional `index`, which has to be in [0, table.len())
+    pub fn read(&self, table: &[f32], index: f64) -> f32 {
+        let length = table.len();
+        let whole = index.floor();
+        let i = whole as usize % length;
+        let t = (index - whole) as f32;
+        let at = |offset: isize| table[(i as isize + offset).rem_euclid(length as isize) as usize];
+
+        match self {
+            Self::None => table[i],
+            Self::Linear => {
+                let (a, b) = (table[i], at(1));
+                a + t * (b - a)
+            },
+            Self::CubicHermite => {
+                let (y0, y1, y2, y3) = (at(-1), table[i], at(1), at(2));
+                let c1 = 0.5 * (y2 - y0);
+                let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+                let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+                ((c3 * t + c2) * t + c1) * t + y1
+            },
+            Self::Lagrange => {
+                let (y0, y1, y2, y3) = (at(-1), table[i], at(1), at(2));
+                let (d0, d1, d2, d3) = (t + 1.0, t, t - 1.0, t - 2.0);
+                -y0 * d1 * d2 * d3 / 6.0
+                    + y1 * d0 * d2 * d3 / 2.0
+                    - y2 * d0 * d1 * d3 / 2.0
+                    + y3 * d0 * d1 * d2 / 6.0
+            },
+            Self::Sinc => {
+                let kernel = sinc_kernel();
+                let position = t * SINC_PHASES as f32;
+                let phase = (position as usize).min(SINC_PHASES - 1);
+                let blend = position - phase as f32;
+                let (current, next) = (&kernel[phase], &kernel[phase + 1]);
+
+                let mut sample = 0.0;
+                for tap in 0..2 * SINC_HALF_TAPS {
+                    let weight = current[tap] + blend * (next[tap] - current[tap]);
+                    sample += weight * at(tap as isize + 1 - SINC_HALF_TAPS as isize);
+                }
+                sample
+            },
+        }
+    }
+}
+
+/// Windowed sinc weights for the taps at offsets `1 - SINC_HALF_TAPS..=SINC_HALF_TAPS`,
+/// one row for every fractional position from 0 to 1 inclusive
+fn sinc_kernel() -> &'static [[f32; 2 * SINC_HALF_TAPS]] {
+    static KERNEL: OnceLock<Vec<[f32; 2 * SINC_HALF_TAPS]>> = OnceLock::new();
+    KERNEL.get_or_init(|| {
+        let half_width = SINC_HALF_TAPS as f64;
+        (0..=SINC_PHASES).map(|phase| {
+            let t = phase as f64 / SINC_PHASES as f64;
+            let mut row = [0.0; 2 * SINC_HALF_TAPS];
+            for (tap, weight) in row.iter_mut().enumerate() {
+                let x = (tap as f64 + 1.0 - half_width) - t;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                // Blackman window spanning the taps
+                let w = PI * (x + half_width) / half_width;
+                let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                *weight = (sinc * window) as f32;
+            }
+            row
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::Interpolation;
+
+    /// Signal to noise ratio of reading a `length` sample table holding `cycles` of a sine between its samples, in decibel
+    fn sine_snr(interpolation: Interpolation, length: usize, cycles: usize) -> f64 {
+        let table: Vec<f32> = (0..length).map(|i| (TAU * (cycles * i) as f64 / length as f64).sin() as f32).collect();
+        let (mut signal, mut noise) = (0.0, 0.0);
+        for step in 0..10007 {
+            // irrational steps so every fractional position gets read
+            let phase = (step as f64 * 0.618_033_988_749_895).fract();
+            let expected = (TAU * cycles as f64 * phase).sin();
+            let read = interpolation.read(&table, phase * length as f64) as f64;
+            signal += expected * expected;
+            noise += (read - expected).powi(2);
+        }
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn snr_against_analytic_sine() {
+        // a single cycle, where the polynomials are at their best
+        for (interpolation, threshold) in [
+            (Interpolation::None, 20.0),
+            (Interpolation::Linear, 55.0),
+            (Interpolation::CubicHermite, 90.0),
+            (Interpolation::Lagrange, 105.0),
+            (Interpolation::Sinc, 80.0),
+        ] {
+            let snr = sine_snr(interpolation, 64, 1);
+            assert!(snr > threshold, "{interpolation:?}: {snr} dB");
+        }
+
+        // every mode improves on the one before it once the sine gets closer to nyquist
+        let mut previous = f64::NEG_INFINITY;
+        for interpolation in Interpolation::ALL {
+            let snr = sine_snr(interpolation, 64, 4);
+            assert!(snr > previous, "{interpolation:?}: {snr} dB, {previous} dB before");
+            previous = snr;
+        }
+
+        // only the sinc stays clean at a quarter of the table's bandwidth
+        assert!(sine_snr(Interpolation::Sinc, 64, 16) > 65.0);
+        assert!(sine_snr(Interpolation::Lagrange, 64, 16) < 30.0);
+    }
+
+    #[test]
+    fn exact_at_table_samples() {
+        let table = [0.25, -1.0, 0.5, 0.75, 0.0, 1.0];
+        for interpolation in Interpolation::ALL {
+            for (i, sample) in table.iter().enumerate() {
+                assert!((interpolation.read(&table, i as f64) - sample).abs() < 1e-4, "{interpolation:?} at {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_around_the_table() {
+        let table = [0.0, 1.0, 2.0, 3.0];
+        assert_eq!(Interpolation::None.read(&table, 3.9), 3.0);
+        assert_eq!(Interpolation::Linear.read(&table, 3.5), 1.5);
+    }
+}
@@ -1,3 +1,11 @@
+pub mod error;
+pub mod expression;
+pub mod fft;
+pub mod interpolation;
+pub mod phase;
+pub mod processing;
+pub mod spectrum;
+pub mod wav;
 pub mod waveforms;
 pub mod wavetable;
 
@@ -0,0 +1,119 @@
+/// Phase of a periodic signal in cycles, kept in [0, 1) and in double precision so it doesn't drift over hours of playback.
+/// Changing the increment never moves the phase, so frequency changes are continuous
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhaseAccumulator {
+    phase: f64,
+    increment: f64, //cycles per sample
+}
+
+impl PhaseAccumulator {
+    pub fn new(phase: f64) -> Self {
+        Self {
+            phase: wrap(phase),
+            increment: 0.0,
+        }
+    }
+
+    pub fn get_phase(&self) -> f64 {
+        self.phase
+    }
+
+    /// Jumps to `phase`, wrapped into [0, 1)
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = wrap(phase);
+    }
+
+    pub fn get_increment(&self) -> f64 {
+        self.increment
+    }
+
+    /// Sets how far the phase moves every sample in cycles, negative increments run backwards
+    pub fn set_increment(&mut self, increment: f64) {
+        self.increment = increment;
+    }
+
+    pub fn set_frequency(&mut self, frequency: f64, sample_rate: u32) {
+        self.increment = frequency / sample_rate as f64;
+    }
+
+    /// How far into a step of `increment` the phase wraps around, in [0, 1], or `None` if it doesn't
+    pub fn wrap_position(&self, increment: f64) -> Option<f64> {
+        let next = self.phase + increment;
+        if increment > 0.0 && next >= 1.0 {
+            Some((1.0 - self.phase) / increment)
+        } else if increment < 0.0 && next < 0.0 {
+            Some(self.phase / -increment)
+        } else {
+            None
+        }
+    }
+
+    /// Moves forward by one sample, returns whether the phase wrapped around
+    pub fn advance(&mut self) -> bool {
+        self.advance_by(self.increment)
+    }
+
+    /// Like [`PhaseAccumulator::advance`] moving by `increment` instead, for increments modulated every sample
+    pub fn advance_by(&mut self, increment: f64) -> bool {
+        self.phase += increment;
+        if (0.0..1.0).contains(&self.phase) {
+            return false;
+        }
+        self.phase = wrap(self.phase);
+        true
+    }
+}
+
+/// Wraps `phase` into [0, 1)
+pub fn wrap(phase: f64) -> f64 {
+    let wrapped = phase - phase.floor();
+    // tiny negative phases round up to exactly 1
+    if wrapped >= 1.0 {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wrap, PhaseAccumulator};
+
+    #[test]
+    fn wraps_both_ways() {
+        let mut phase = PhaseAccumulator::new(0.75);
+        phase.set_increment(0.125);
+        assert_eq!((phase.wrap_position(0.125), phase.wrap_position(0.5)), (None, Some(0.5)));
+        assert_eq!(phase.wrap_position(-1.0), Some(0.75));
+        assert!(!phase.advance());
+        assert!(phase.advance());
+        assert_eq!(phase.get_phase(), 0.0);
+
+        phase.set_increment(-0.25);
+        assert!(phase.advance());
+        assert_eq!(phase.get_phase(), 0.75);
+
+        assert_eq!(wrap(-1e-20), 0.0);
+        assert_eq!(wrap(3.5), 0.5);
+    }
+
+    #[test]
+    fn no_drift_over_an_hour() {
+        // 440 Hz at 8 kHz isn't exactly representable, so every step rounds
+        const SAMPLE_RATE: u64 = 8000;
+        const FREQUENCY: u64 = 440;
+        let mut phase = PhaseAccumulator::default();
+        phase.set_frequency(FREQUENCY as f64, SAMPLE_RATE as u32);
+
+        // ends in the middle of a cycle, so the wrap count is unambiguous
+        let samples = SAMPLE_RATE * 60 * 60 + 3;
+        let mut wraps = 0;
+        for _ in 0..samples {
+            wraps += phase.advance() as u64;
+        }
+        let exact = (samples * FREQUENCY % SAMPLE_RATE) as f64 / SAMPLE_RATE as f64;
+        let error = (phase.get_phase() - exact).abs();
+        assert!(error < 1e-6, "drifted by {error} cycles");
+        assert_eq!(wraps, samples * FREQUENCY / SAMPLE_RATE);
+    }
+}
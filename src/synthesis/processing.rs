@@ -0,0 +1,280 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::math::lerp;
+
+use super::{spectrum::{Harmonic, Spectrum}, wavetable::WaveTable};
+
+/// Harmonics quieter than this are analysis noise, their phase doesn't mean anything
+const SILENT_HARMONIC: f32 = 1e-5;
+
+/*
+ * operations for preparing tables, none of them change the table they're called on
+ */
+
+impl WaveTable {
+    /// A copy with `process` applied to the fullest level of every frame
+    fn map_frames<F>(&self, mut process: F) -> Self
+        where F: FnMut(&[f32]) -> Vec<f32>,
+    {
+        let frames = (0..self.frame_count()).map(|frame| process(self.get_frame_level(frame, 0))).collect();
+        Self::from_frames(frames).with_interpolation(self.get_interpolation())
+    }
+
+    /// A copy with every frame multiplied by `gain`
+    fn scaled(&self, gain: f32) -> Self {
+        self.map_frames(|frame| frame.iter().map(|sample| sample * gain).collect())
+    }
+
+    fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.frame_count()).flat_map(|frame| self.get_frame_level(frame, 0).iter().copied())
+    }
+
+    /// Scales the table so its loudest sample reaches `peak`.
+    /// Every frame gets the same gain, so their relative levels stay the same. Silent tables are left alone
+    pub fn normalize_peak(&self, peak: f32) -> Self {
+        let max = self.samples().fold(0.0, |max, sample| sample.abs().max(max));
+        if max == 0.0 {
+            return self.clone();
+        }
+        self.scaled(peak / max)
+    }
+
+    /// Scales the table to an RMS level of `rms` over all of its frames. Silent tables are left alone
+    pub fn normalize_rms(&self, rms: f32) -> Self {
+        let (sum, count) = self.samples().fold((0.0, 0), |(sum, count), sample| (sum + (sample * sample) as f64, count + 1));
+        let current = (sum / count as f64).sqrt() as f32;
+        if current == 0.0 {
+            return self.clone();
+        }
+        self.scaled(rms / current)
+    }
+
+    /// Removes the offset of every frame, so none of them push a DC offset into the output
+    pub fn remove_dc(&self) -> Self {
+        self.map_frames(|frame| {
+            let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+            frame.iter().map(|sample| sample - mean).collect()
+        })
+    }
+
+    /// Rotates every frame so the first one starts on a rising zero crossing, so notes start without a click.
+    /// All frames move by the same amount to keep them in phase with each other. Tables that never cross zero are left alone,
+    /// so DC should be removed first
+    pub fn align_zero_crossing(&self) -> Self {
+        let first = self.get_frame_level(0, 0);
+        let length = first.len();
+        let crossing = (0..length).find_map(|i| {
+            let (current, next) = (first[i], first[(i + 1) % length]);
+            // the fraction between the samples where the frame reaches zero
+            (current <= 0.0 && next > 0.0).then(|| (i as f32 + current / (current - next)) / length as f32)
+        });
+        let Some(crossing) = crossing else {
+            return self.clone();
+        };
+        // exact for the band-limited frames, unlike moving whole samples
+        self.map_frames(|frame| {
+            let mut spectrum = Spectrum::analyze(frame);
+            for (index, harmonic) in spectrum.get_harmonics_mut().iter_mut().enumerate() {
+                harmonic.phase += TAU * (index + 1) as f32 * crossing;
+            }
+            spectrum.synthesize(length)
+        })
+    }
+
+    /// Averages every sample of every frame with the `radius` samples on either side, wrapping around the cycle.
+    /// A gentle low-pass that rounds off sharp corners, the offset and the length of the frames stay the same. A radius of 0 changes nothing
+    pub fn smooth(&self, radius: usize) -> Self {
+        if radius == 0 {
+            return self.clone();
+        }
+        self.map_frames(|frame| {
+            let length = frame.len();
+            let width = 2 * radius + 1;
+            // a running sum over the window, starting with the one around the first sample
+            let mut sum: f32 = (0..width).map(|offset| frame[(offset + length * width - radius) % length]).sum();
+            (0..length).map(|i| {
+                let average = sum / width as f32;
+                sum += frame[(i + radius + 1) % length] - frame[(i + length * width - radius) % length];
+                average
+            }).collect()
+        })
+    }
+
+    /// Every frame resynthesized at `length` samples, dropping harmonics that don't fit
+    pub fn resample(&self, length: usize) -> Self {
+        self.map_frames(|frame| Spectrum::analyze(frame).synthesize(length))
+    }
+
+    /// A table of `frames` frames fading from the first frame of `self` into the first frame of `other` sample by sample,
+    /// `other` is resampled to the length of `self` if needed. Will panic if `frames` is 0
+    pub fn crossfade(&self, other: &WaveTable, frames: usize) -> Self {
+        let from = self.get_frame_level(0, 0);
+        let to = Spectrum::analyze(other.get_frame_level(0, 0)).synthesize(self.len());
+        let sequence = (0..frames).map(|frame| {
+            let amount = morph_amount(frame, frames);
+            from.iter().zip(to.iter()).map(|(a, b)| lerp(*a, *b, amount)).collect()
+        }).collect();
+        Self::from_frames(sequence).with_interpolation(self.get_interpolation())
+    }
+
+    /// Like [`WaveTable::crossfade`], interpolating the amplitude and phase of every harmonic instead of the samples.
+    /// Harmonics that are out of phase turn into each other instead of cancelling out halfway
+    pub fn spectral_morph(&self, other: &WaveTable, frames: usize) -> Self {
+        let (from, to) = (self.analyze(0), other.analyze(0));
+        let count = from.get_harmonics().len().max(to.get_harmonics().len());
+        let spectra: Vec<Spectrum> = (0..frames).map(|frame| {
+            let amount = morph_amount(frame, frames);
+            let harmonics = (0..count).map(|index| {
+                let a = from.get_harmonics().get(index).copied().unwrap_or_default();
+                let b = to.get_harmonics().get(index).copied().unwrap_or_default();
+                // a missing harmonic fades in at the phase it ends up with
+                let (a_phase, b_phase) = match (a.amplitude < SILENT_HARMONIC, b.amplitude < SILENT_HARMONIC) {
+                    (true, false) => (b.phase, b.phase),
+                    (false, true) => (a.phase, a.phase),
+                    _ => (a.phase, b.phase),
+                };
+                // the shorter way around
+                let turn = (b_phase - a_phase + PI).rem_euclid(TAU) - PI;
+                Harmonic::new(lerp(a.amplitude, b.amplitude, amount), a_phase + turn * amount)
+            }).collect();
+            let mut spectrum = Spectrum::new(harmonics);
+            spectrum.set_offset(lerp(from.get_offset(), to.get_offset(), amount));
+            spectrum
+        }).collect();
+        Self::from_spectra(&spectra, self.len()).with_interpolation(self.get_interpolation())
+    }
+}
+
+/// How far along a morph sequence of `frames` frames `frame` is, from 0 at the first to 1 at the last
+fn morph_amount(frame: usize, frames: usize) -> f32 {
+    frame as f32 / frames.saturating_sub(1).max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use crate::synthesis::{interpolation::Interpolation, waveforms::WaveForm, wavetable::WaveTable};
+
+    fn peak(table: &[f32]) -> f32 {
+        table.iter().fold(0.0, |max, sample| sample.abs().max(max))
+    }
+
+    fn mean(table: &[f32]) -> f32 {
+        table.iter().sum::<f32>() / table.len() as f32
+    }
+
+    #[test]
+    fn normalizes_and_removes_dc() {
+        // too loud, with an offset of 0.5
+        let wavetable = WaveTable::from_fn(|phase| 0.5 + 2.0 * phase.sin() + 0.5 * (3.0 * phase).sin(), 256);
+        assert!((mean(wavetable.get_level(0)) - 0.5).abs() < 1e-5);
+
+        let centered = wavetable.remove_dc();
+        assert!(mean(centered.get_level(0)).abs() < 1e-6);
+
+        let normalized = centered.normalize_peak(1.0);
+        assert!((peak(normalized.get_level(0)) - 1.0).abs() < 1e-4);
+
+        let normalized = centered.normalize_rms(0.5);
+        let rms = (normalized.get_level(0).iter().map(|sample| sample * sample).sum::<f32>() / 256.0).sqrt();
+        assert!((rms - 0.5).abs() < 1e-4);
+
+        let silent = WaveTable::from_fn(|_| 0.0, 64);
+        assert_eq!(peak(silent.normalize_peak(1.0).get_level(0)), 0.0);
+    }
+
+    #[test]
+    fn gain_is_shared_between_frames() {
+        let wavetable = WaveTable::from_frames_fn(|position, phase| (1.0 + position) * phase.sin(), 128, 2).normalize_peak(1.0);
+        assert!((peak(wavetable.get_frame_level(0, 0)) - 0.5).abs() < 1e-4);
+        assert!((peak(wavetable.get_frame_level(1, 0)) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn aligns_to_a_rising_zero_crossing() {
+        let wavetable = WaveTable::from_frames_fn(|position, phase| (phase + 1.0 + position).sin(), 128, 2)
+            .with_interpolation(Interpolation::CubicHermite)
+            .align_zero_crossing();
+        assert_eq!(wavetable.get_interpolation(), Interpolation::CubicHermite);
+        let first = wavetable.get_level(0);
+        assert!(first[0].abs() < 1e-5 && first[1] > 0.0);
+        // the second frame moved by the same amount, so it still leads the first by a radian
+        let second = wavetable.get_frame_level(1, 0);
+        assert!((second[0] - 1f32.sin()).abs() < 1e-5);
+
+        let positive = WaveTable::from_fn(|phase| 2.0 + phase.sin(), 64);
+        assert_eq!(positive.align_zero_crossing().get_level(0), positive.get_level(0));
+    }
+
+    #[test]
+    fn smoothing_is_a_low_pass() {
+        let length = 256;
+        let wavetable = WaveTable::from_fn(|phase| 0.25 + phase.sin() + (32.0 * phase).sin(), length);
+        let smooth = wavetable.smooth(3);
+        assert_eq!(smooth.len(), length);
+        assert!((mean(smooth.get_level(0)) - 0.25).abs() < 1e-5);
+
+        // a moving average of n samples scales harmonic k by sin(pi k n / length) / (n sin(pi k / length))
+        let gain = |harmonic: f32| (PI * harmonic * 7.0 / length as f32).sin() / (7.0 * (PI * harmonic / length as f32).sin());
+        let harmonics = smooth.analyze(0);
+        let harmonics = harmonics.get_harmonics();
+        assert!((harmonics[0].amplitude - gain(1.0)).abs() < 1e-4);
+        assert!((harmonics[31].amplitude - gain(32.0)).abs() < 1e-4);
+        assert!(harmonics[31].amplitude < 0.5 && harmonics[0].amplitude > 0.99);
+
+        assert_eq!(wavetable.smooth(0).get_level(0), wavetable.get_level(0));
+    }
+
+    #[test]
+    fn resamples_without_aliasing() {
+        let saw = WaveTable::from_waveform(WaveForm::Saw, 256).unwrap();
+        let short = saw.resample(64);
+        assert_eq!(short.len(), 64);
+        // the saw keeps its harmonics up to the new Nyquist
+        let harmonics = short.analyze(0);
+        assert_eq!(harmonics.get_harmonics().len(), 31);
+        for (index, harmonic) in harmonics.get_harmonics().iter().enumerate() {
+            assert!((harmonic.amplitude - saw.analyze(0).get_harmonics()[index].amplitude).abs() < 1e-4);
+        }
+
+        let sine = WaveTable::from_waveform(WaveForm::Sine, 100).unwrap().resample(300);
+        for (i, sample) in sine.get_level(0).iter().enumerate() {
+            assert!((sample - (TAU * i as f32 / 300.0).sin()).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn crossfades_cancel_where_spectral_morphs_rotate() {
+        let sine = WaveTable::from_waveform(WaveForm::Sine, 128).unwrap();
+        let inverted = WaveTable::from_fn(|phase| -phase.sin(), 64);
+
+        let crossfade = sine.crossfade(&inverted, 5);
+        assert_eq!((crossfade.frame_count(), crossfade.len()), (5, 128));
+        assert!(peak(crossfade.get_frame_level(2, 0)) < 1e-5);
+        assert!((crossfade.get_frame_level(1, 0)[32] - 0.5).abs() < 1e-4);
+        assert!((crossfade.get_frame_level(4, 0)[32] + 1.0).abs() < 1e-4);
+
+        let morph = sine.spectral_morph(&inverted, 5);
+        assert_eq!(morph.frame_count(), 5);
+        for frame in 0..5 {
+            let harmonic = morph.analyze(frame).get_harmonics()[0];
+            assert!((harmonic.amplitude - 1.0).abs() < 1e-4, "frame {frame}: {harmonic:?}");
+        }
+        // halfway between the phases of a sine and its inverse
+        let middle = morph.analyze(2).get_harmonics()[0].phase.rem_euclid(PI);
+        assert!((middle - PI / 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn spectral_morph_blends_harmonics() {
+        let fundamental = WaveTable::from_waveform(WaveForm::Sine, 64).unwrap();
+        let third = WaveTable::from_fn(|phase| (3.0 * phase).sin(), 64);
+        let morph = fundamental.spectral_morph(&third, 3);
+        let middle = morph.analyze(1);
+        let amplitudes: Vec<f32> = middle.get_harmonics()[..3].iter().map(|harmonic| harmonic.amplitude).collect();
+        assert!((amplitudes[0] - 0.5).abs() < 1e-4 && amplitudes[1].abs() < 1e-4 && (amplitudes[2] - 0.5).abs() < 1e-4);
+        // both harmonics stay in sine phase
+        assert!(middle.get_harmonics()[0].phase.abs() < 1e-4 && middle.get_harmonics()[2].phase.abs() < 1e-4);
+    }
+}
@@ -0,0 +1,202 @@
+use std::f64::consts::FRAC_PI_2;
+
+use super::fft::{real_fft, real_ifft, Complex};
+
+/// One sine partial of a single cycle waveform
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Harmonic {
+    pub amplitude: f32,
+    pub phase: f32, //radians, 0 starts like a sine
+}
+
+impl Harmonic {
+    pub fn new(amplitude: f32, phase: f32) -> Self {
+        Self {
+            amplitude,
+            phase,
+        }
+    }
+}
+
+/// A single cycle waveform as a sum of harmonics, `harmonics[0]` is the fundamental, `harmonics[k]` has `k + 1` cycles per cycle.
+/// Resynthesizing only ever writes harmonics that fit the table, so the result is free of aliasing
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spectrum {
+    offset: f32, //DC
+    harmonics: Vec<Harmonic>,
+}
+
+impl Spectrum {
+    pub fn new(harmonics: Vec<Harmonic>) -> Self {
+        Self {
+            offset: 0.0,
+            harmonics,
+        }
+    }
+
+    /// Harmonics in sine phase with `amplitudes`, starting at the fundamental
+    pub fn from_amplitudes(amplitudes: &[f32]) -> Self {
+        Self::new(amplitudes.iter().map(|amplitude| Harmonic::new(*amplitude, 0.0)).collect())
+    }
+
+    /// Measures every harmonic of a single cycle stored in `table`, up to the highest one it can hold
+    pub fn analyze(table: &[f32]) -> Self {
+        let length = table.len();
+        if length == 0 {
+            return Self::default();
+        }
+        let spectrum = real_fft(table);
+        let scale = 2.0 / length as f64;
+        // the bin at exactly Nyquist has no phase to speak of, so it's left out
+        let harmonics = spectrum[1..length.div_ceil(2)].iter().map(|bin| {
+            // a sine shows up a quarter turn behind a cosine
+            Harmonic::new((bin.magnitude() * scale) as f32, (bin.phase() + FRAC_PI_2) as f32)
+        }).collect();
+        Self {
+            offset: (spectrum[0].re / length as f64) as f32,
+            harmonics,
+        }
+    }
+
+    /// A single cycle of `length` samples holding every harmonic below Nyquist, higher ones are dropped
+    pub fn synthesize(&self, length: usize) -> Vec<f32> {
+        if length == 0 {
+            return Vec::new();
+        }
+        let mut spectrum = vec![Complex::ZERO; length];
+        spectrum[0] = Complex::new(self.offset as f64 * length as f64, 0.0);
+        let scale = length as f64 / 2.0;
+        for (index, harmonic) in self.harmonics.iter().enumerate().take(length.div_ceil(2) - 1) {
+            let number = index + 1;
+            let bin = Complex::from_polar(harmonic.amplitude as f64 * scale, harmonic.phase as f64 - FRAC_PI_2);
+            spectrum[number] = bin;
+            spectrum[length - number] = bin.conj();
+        }
+        real_ifft(&spectrum)
+    }
+
+    pub fn get_offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn set_offset(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    pub fn get_harmonics(&self) -> &[Harmonic] {
+        &self.harmonics
+    }
+
+    pub fn get_harmonics_mut(&mut self) -> &mut Vec<Harmonic> {
+        &mut self.harmonics
+    }
+
+    /// Silences every even harmonic, turning a saw into a square
+    pub fn remove_even_harmonics(&mut self) {
+        for harmonic in self.harmonics.iter_mut().skip(1).step_by(2) {
+            harmonic.amplitude = 0.0;
+        }
+    }
+
+    /// Changes every harmonic by `db_per_octave` for each octave it is above the fundamental
+    pub fn tilt(&mut self, db_per_octave: f32) {
+        for (index, harmonic) in self.harmonics.iter_mut().enumerate() {
+            let octaves = ((index + 1) as f32).log2();
+            harmonic.amplitude *= 10f32.powf(db_per_octave * octaves / 20.0);
+        }
+    }
+
+    /// Stretches the amplitude envelope by `factor` while the harmonics stay put,
+    /// so resonances move up or down without changing the pitch. Phases are kept
+    pub fn shift_formants(&mut self, factor: f32) {
+        let envelope: Vec<f32> = self.harmonics.iter().map(|harmonic| harmonic.amplitude).collect();
+        let amplitude_at = |number: f32| -> f32 {
+            // between the harmonics of the original envelope, silent outside of it
+            let index = number - 1.0;
+            if !(0.0..=(envelope.len() - 1) as f32).contains(&index) {
+                return 0.0;
+            }
+            let lower = index as usize;
+            let next = envelope.get(lower + 1).copied().unwrap_or(0.0);
+            envelope[lower] + (index - lower as f32) * (next - envelope[lower])
+        };
+        for (index, harmonic) in self.harmonics.iter_mut().enumerate() {
+            harmonic.amplitude = amplitude_at((index + 1) as f32 / factor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use super::{Harmonic, Spectrum};
+
+    fn assert_close(a: &[f32], b: &[f32], tolerance: f32) {
+        assert_eq!(a.len(), b.len());
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            assert!((a - b).abs() < tolerance, "{a} and {b} at {i}");
+        }
+    }
+
+    #[test]
+    fn synthesizes_the_analytic_sum() {
+        let spectrum = Spectrum::new(vec![Harmonic::new(1.0, 0.0), Harmonic::new(0.0, 0.0), Harmonic::new(0.5, PI / 3.0)]);
+        let table = spectrum.synthesize(100);
+        let expected: Vec<f32> = (0..100).map(|i| {
+            let phase = TAU * i as f32 / 100.0;
+            phase.sin() + 0.5 * (3.0 * phase + PI / 3.0).sin()
+        }).collect();
+        assert_close(&table, &expected, 1e-5);
+    }
+
+    #[test]
+    fn analysis_round_trips() {
+        let table: Vec<f32> = (0..256).map(|i| ((i * 37 % 101) as f32 / 50.0 - 1.0) * 0.5).collect();
+        let spectrum = Spectrum::analyze(&table);
+        assert_eq!(spectrum.get_harmonics().len(), 127);
+        // everything but the Nyquist bin comes back
+        let nyquist: f32 = table.iter().enumerate().map(|(i, sample)| if i % 2 == 0 { *sample } else { -sample }).sum::<f32>() / 256.0;
+        let expected: Vec<f32> = table.iter().enumerate().map(|(i, sample)| sample - if i % 2 == 0 { nyquist } else { -nyquist }).collect();
+        assert_close(&spectrum.synthesize(256), &expected, 1e-5);
+
+        let shifted: Vec<f32> = (0..64).map(|i| 0.25 + 0.75 * (TAU * 2.0 * i as f32 / 64.0 + 1.0).sin()).collect();
+        let spectrum = Spectrum::analyze(&shifted);
+        assert!((spectrum.get_offset() - 0.25).abs() < 1e-6);
+        let second = spectrum.get_harmonics()[1];
+        assert!((second.amplitude - 0.75).abs() < 1e-5 && (second.phase - 1.0).abs() < 1e-5, "{second:?}");
+    }
+
+    #[test]
+    fn drops_harmonics_above_nyquist() {
+        let mut amplitudes = vec![0.0; 40];
+        amplitudes[39] = 1.0;
+        assert!(Spectrum::from_amplitudes(&amplitudes).synthesize(64).iter().all(|sample| sample.abs() < 1e-6));
+        assert!(Spectrum::from_amplitudes(&amplitudes).synthesize(128).iter().any(|sample| sample.abs() > 0.9));
+    }
+
+    #[test]
+    fn spectral_edits() {
+        let saw: Vec<f32> = (1..=16).map(|number| 1.0 / number as f32).collect();
+        let mut spectrum = Spectrum::from_amplitudes(&saw);
+        spectrum.remove_even_harmonics();
+        for (index, harmonic) in spectrum.get_harmonics().iter().enumerate() {
+            let expected = if index % 2 == 0 { saw[index] } else { 0.0 };
+            assert_eq!(harmonic.amplitude, expected);
+        }
+
+        let mut flat = Spectrum::from_amplitudes(&[1.0; 8]);
+        flat.tilt(-6.0);
+        let amplitudes: Vec<f32> = flat.get_harmonics().iter().map(|harmonic| harmonic.amplitude).collect();
+        assert_close(&[amplitudes[0], amplitudes[1], amplitudes[3], amplitudes[7]], &[1.0, 0.501, 0.251, 0.126], 1e-3);
+
+        // a resonance at the 4th harmonic moves up an octave
+        let mut formant = Spectrum::from_amplitudes(&[0.1, 0.2, 0.5, 1.0, 0.5, 0.2, 0.1, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        formant.shift_formants(2.0);
+        let peak = formant.get_harmonics().iter().enumerate()
+            .max_by(|a, b| a.1.amplitude.total_cmp(&b.1.amplitude))
+            .unwrap().0;
+        assert_eq!(peak + 1, 8);
+        assert_eq!(formant.get_harmonics().len(), 12);
+    }
+}
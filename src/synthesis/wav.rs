@@ -0,0 +1,243 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}, path::Path};
+
+use crate::{audio::wav::{read_wav, SampleFormat, WavChunk, WavFile, WavSpec}, error::{AudioError, SynthesisError}, Error, Result};
+
+use super::wavetable::WaveTable;
+
+/// Frame size written by Serum and Vital, and the first guess for files that don't say
+pub const SERUM_FRAME_SIZE: usize = 2048;
+
+/// Wavetables have no sample rate of their own, WAV files need one anyway
+const EXPORT_SAMPLE_RATE: u32 = 44100;
+
+/// Frame sizes tried in order for files without a `clm ` chunk or a given frame size
+const GUESSED_FRAME_SIZES: [usize; 5] = [SERUM_FRAME_SIZE, 1024, 4096, 512, 256];
+
+/// Reads the frame size from the text of a `clm ` chunk, which starts like `<!>2048 01000000 wavetable (www.xferrecords.com)`
+pub fn clm_frame_size(chunk: &[u8]) -> Result<usize> {
+    let text = String::from_utf8_lossy(chunk);
+    let invalid = || Error::Synthesis(SynthesisError::SynthesisInvalidClmChunk(text.to_string()));
+    text.strip_prefix("<!>")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|size| size.parse().ok())
+        .ok_or_else(invalid)
+}
+
+/// A `clm ` chunk telling other synths the frame size, in the format Serum writes
+pub fn clm_chunk(frame_size: usize) -> WavChunk {
+    WavChunk {
+        id: *b"clm ",
+        data: format!("<!>{frame_size} 00000000 wavetable (wavetable_synthesizer)").into_bytes(),
+    }
+}
+
+/// The first of the common frame sizes that `samples` can be split into evenly.
+/// Arbitrary audio isn't taken as a single cycle, transforming a frame that long would take forever
+fn guess_frame_size(samples: usize) -> Option<usize> {
+    GUESSED_FRAME_SIZES.into_iter()
+        .find(|size| samples >= *size && samples.is_multiple_of(*size))
+}
+
+/// Splits a WAV file into the frames of a wavetable, mixing every channel down to mono.
+/// The frame size comes from the `clm ` chunk if there is one, otherwise `frame_size` or a guess is used.
+/// Files no common frame size fits need an explicit one, without it they are reported as having frame size 0
+pub fn wavetable_from_wav(file: &WavFile, frame_size: Option<usize>) -> Result<WaveTable> {
+    let channels = file.spec.channels as usize;
+    let samples: Vec<f32> = file.samples.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    if samples.is_empty() {
+        return Err(Error::Synthesis(SynthesisError::SynthesisEmptyWavetable));
+    }
+
+    let frame_size = match file.get_chunk(b"clm ") {
+        Some(chunk) => clm_frame_size(&chunk.data)?,
+        None => frame_size.or_else(|| guess_frame_size(samples.len())).unwrap_or(0),
+    };
+    if frame_size == 0 || !samples.len().is_multiple_of(frame_size) {
+        return Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(frame_size, samples.len())));
+    }
+    Ok(WaveTable::from_frames(samples.chunks(frame_size).map(<[f32]>::to_vec).collect()))
+}
+
+/// The fullest level of every frame one after the other as a mono 32 bit float file, with a `clm ` chunk holding the frame size.
+/// That level is already band-limited, so whatever the source frames had at Nyquist is lost on the first export.
+/// Everything below survives, and a table read back from its export exports to the same samples again
+pub fn wavetable_to_wav(wavetable: &WaveTable) -> WavFile {
+    let samples = (0..wavetable.frame_count())
+        .flat_map(|frame| wavetable.get_frame_level(frame, 0).iter().copied())
+        .collect();
+    WavFile {
+        spec: WavSpec {
+            sample_rate: EXPORT_SAMPLE_RATE,
+            channels: 1,
+            format: SampleFormat::Float32,
+        },
+        samples,
+        chunks: vec![clm_chunk(wavetable.len())],
+    }
+}
+
+/// Writes `wavetable` as a WAV file to `writer`, see [`wavetable_to_wav`]
+pub fn write_wavetable<W: Write>(writer: W, wavetable: &WaveTable) -> Result<W> {
+    wavetable_to_wav(wavetable).write(writer)
+}
+
+/// Writes `wavetable` into a new WAV file at `path`, replacing it if it exists
+pub fn write_wavetable_file(path: impl AsRef<Path>, wavetable: &WaveTable) -> Result<()> {
+    let file = File::create(path).map_err(|err| Error::Audio(AudioError::AudioFileWrite(err.to_string())))?;
+    write_wavetable(BufWriter::new(file), wavetable)?;
+    Ok(())
+}
+
+/// Reads a wavetable from a WAV file in `reader`, see [`wavetable_from_wav`]
+pub fn read_wavetable<R: Read>(reader: R, frame_size: Option<usize>) -> Result<WaveTable> {
+    wavetable_from_wav(&read_wav(reader)?, frame_size)
+}
+
+/// Reads a wavetable from the WAV file at `path`, see [`wavetable_from_wav`]
+pub fn read_wavetable_file(path: impl AsRef<Path>, frame_size: Option<usize>) -> Result<WaveTable> {
+    let file = File::open(path).map_err(|err| Error::Audio(AudioError::AudioFileRead(err.to_string())))?;
+    read_wavetable(BufReader::new(file), frame_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use crate::{audio::wav::{write_wav, SampleFormat, WavSpec}, error::SynthesisError, synthesis::{waveforms::WaveForm, wavetable::MipLevel}, Error};
+    use crate::synthesis::wavetable::WaveTable;
+    use super::{clm_chunk, clm_frame_size, read_wavetable, read_wavetable_file, wavetable_to_wav, write_wavetable, write_wavetable_file};
+
+    /// `frames` frames of `frame_size` samples fading from a sine to a saw, as a float WAV file
+    fn fixture(frame_size: usize, frames: usize, channels: u16, clm: Option<&str>) -> Vec<u8> {
+        let mut samples = Vec::new();
+        for frame in 0..frames {
+            let position = frame as f32 / (frames - 1).max(1) as f32;
+            for i in 0..frame_size {
+                let phase = TAU * i as f32 / frame_size as f32;
+                let sample = (1.0 - position) * phase.sin() + position * (phase / TAU * 2.0 - 1.0);
+                samples.extend(std::iter::repeat_n(sample * 0.5, channels as usize));
+            }
+        }
+        let spec = WavSpec { sample_rate: 44100, channels, format: SampleFormat::Float32 };
+        let mut bytes = write_wav(Vec::new(), spec, &samples).unwrap();
+        if let Some(clm) = clm {
+            // after the data chunk, which readers have to accept
+            bytes.extend_from_slice(b"clm ");
+            bytes.extend_from_slice(&(clm.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(clm.as_bytes());
+            if clm.len() % 2 == 1 {
+                bytes.push(0);
+            }
+            let riff_length = bytes.len() as u32 - 8;
+            bytes[4..8].copy_from_slice(&riff_length.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_clm_chunks() {
+        assert_eq!(clm_frame_size(b"<!>2048 01000000 wavetable (www.xferrecords.com)").unwrap(), 2048);
+        assert_eq!(clm_frame_size(b"<!>256 10000000 wavetable (vital.audio)").unwrap(), 256);
+        assert_eq!(clm_frame_size(&clm_chunk(1024).data).unwrap(), 1024);
+        assert!(matches!(clm_frame_size(b"2048"), Err(Error::Synthesis(SynthesisError::SynthesisInvalidClmChunk(_)))));
+        assert!(matches!(clm_frame_size(b"<!>abc"), Err(Error::Synthesis(SynthesisError::SynthesisInvalidClmChunk(_)))));
+    }
+
+    #[test]
+    fn frame_size_comes_from_the_clm_chunk() {
+        let bytes = fixture(256, 6, 1, Some("<!>256 10000000 wavetable (vital.audio)"));
+        // the chunk wins over the given size
+        let wavetable = read_wavetable(bytes.as_slice(), Some(512)).unwrap();
+        assert_eq!((wavetable.frame_count(), wavetable.len()), (6, 256));
+        assert_eq!(wavetable.get_waveform(), WaveForm::Table);
+
+        let level = MipLevel::default();
+        for i in 0..256 {
+            let phase = i as f64 / 256.0;
+            let sine = 0.5 * (TAU * phase as f32).sin();
+            assert!((wavetable.lookup_band_limited(phase, level, 0.0) - sine).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn frame_size_falls_back_to_given_then_guessed() {
+        let bytes = fixture(150, 4, 2, None);
+        let wavetable = read_wavetable(bytes.as_slice(), Some(150)).unwrap();
+        assert_eq!((wavetable.frame_count(), wavetable.len()), (4, 150));
+        // nothing common divides 600 samples, so there's nothing to guess
+        assert!(matches!(read_wavetable(bytes.as_slice(), None), Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(0, 600)))));
+        // neither does a long recording, which used to become a single cycle far too long to transform
+        let recording = fixture(44101, 1, 1, None);
+        assert!(matches!(read_wavetable(recording.as_slice(), None), Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(0, 44101)))));
+
+        let serum = read_wavetable(fixture(2048, 3, 1, None).as_slice(), None).unwrap();
+        assert_eq!((serum.frame_count(), serum.len()), (3, 2048));
+        let short = read_wavetable(fixture(512, 3, 1, None).as_slice(), None).unwrap();
+        assert_eq!((short.frame_count(), short.len()), (3, 512));
+    }
+
+    #[test]
+    fn malformed_tables_are_errors() {
+        let bytes = fixture(256, 3, 1, None);
+        assert!(matches!(read_wavetable(bytes.as_slice(), Some(500)), Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(500, 768)))));
+        assert!(matches!(read_wavetable(bytes.as_slice(), Some(0)), Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(0, 768)))));
+
+        let bytes = fixture(256, 3, 1, Some("<!>1000 wavetable"));
+        assert!(matches!(read_wavetable(bytes.as_slice(), None), Err(Error::Synthesis(SynthesisError::SynthesisInvalidFrameSize(1000, 768)))));
+
+        let empty = fixture(0, 1, 1, None);
+        assert!(matches!(read_wavetable(empty.as_slice(), None), Err(Error::Synthesis(SynthesisError::SynthesisEmptyWavetable))));
+        assert!(matches!(read_wavetable(&b"not a wav file"[..], None), Err(Error::Audio(_))));
+    }
+
+    #[test]
+    fn reads_fixture_files() {
+        let path = std::env::temp_dir().join(format!("wavetable_import_{}.wav", std::process::id()));
+        std::fs::write(&path, fixture(2048, 2, 1, Some("<!>2048 01000000 wavetable (www.xferrecords.com)"))).unwrap();
+        let wavetable = read_wavetable_file(&path, None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(wavetable.unwrap().frame_count(), 2);
+
+        assert!(matches!(read_wavetable_file(&path, None), Err(Error::Audio(_))));
+    }
+
+    #[test]
+    fn round_trips_through_the_importer() {
+        let wavetable = WaveTable::from_frames_fn(|position, phase| (phase * (1.0 + 7.0 * position)).sin() * phase.cos(), 256, 5);
+        let bytes = write_wavetable(Vec::new(), &wavetable).unwrap();
+        // a frame size the file has to override
+        let read = read_wavetable(bytes.as_slice(), Some(128)).unwrap();
+        assert_eq!((read.frame_count(), read.len(), read.level_count()), (5, 256, wavetable.level_count()));
+        for frame in 0..5 {
+            for level in 0..read.level_count() {
+                let (written, read) = (wavetable.get_frame_level(frame, level), read.get_frame_level(frame, level));
+                assert!(written.iter().zip(read).all(|(a, b)| (a - b).abs() < 1e-5), "frame {frame}, level {level}");
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("wavetable_export_{}.wav", std::process::id()));
+        let saw = WaveTable::from_waveform(WaveForm::Saw, 2048).unwrap();
+        write_wavetable_file(&path, &saw).unwrap();
+        let read = read_wavetable_file(&path, None);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read.unwrap().get_level(0).iter().zip(saw.get_level(0)).all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn export_drops_nyquist_once() {
+        // a sine with a harmonic right at Nyquist
+        let source: Vec<f32> = (0..256).map(|i| (TAU * i as f32 / 256.0).sin() + if i % 2 == 0 { 0.5 } else { -0.5 }).collect();
+        let wavetable = WaveTable::from_frames(vec![source.clone()]);
+        let exported = wavetable_to_wav(&wavetable).samples;
+        for (i, (exported, source)) in exported.iter().zip(&source).enumerate() {
+            let nyquist = if i % 2 == 0 { 0.5 } else { -0.5 };
+            assert!((exported - (source - nyquist)).abs() < 1e-5, "{exported} instead of {} at {i}", source - nyquist);
+        }
+
+        let bytes = write_wavetable(Vec::new(), &wavetable).unwrap();
+        let again = wavetable_to_wav(&read_wavetable(bytes.as_slice(), None).unwrap()).samples;
+        assert!(again.iter().zip(&exported).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+}
@@ -1,11 +1,27 @@
 use std::f32::consts::{PI, TAU};
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+use crate::{error::SynthesisError, Error, Result};
+
+/// Shapes a [`WaveTable`](super::wavetable::WaveTable) can be built from, parameters are kept so a table can describe itself
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub enum WaveForm {
     Sine,
     Triangle,
     Square,
     Saw,
+    /// High for `width` of the cycle, in [0, 1]
+    Pulse { width: f32 },
+    /// A triangle peaking at `skew` of the cycle, in [0, 1]. 0.5 is a triangle, 1 a saw and 0 a falling saw
+    SkewedTriangle { skew: f32 },
+    /// A sine pushed towards a square, `shape` in [0, 1] goes from sine to square
+    SineToSquare { shape: f32 },
+    /// The positive half of a sine, silent for the rest of the cycle
+    HalfRectifiedSine,
+    /// The magnitude of a sine, an octave up
+    FullRectifiedSine,
+    /// A triangle clipped flat, `slope` in [0, 1] goes from square to triangle
+    Trapezoid { slope: f32 },
+    /// Loaded or computed samples, without a function behind them
     Table,
 }
 
@@ -39,39 +55,227 @@ pub fn saw(x: f32) -> f32 {
     ((x + PI) / PI) % 2.0 - 1.0
 }
 
+#[allow(unused)]
+pub fn pulse(x: f32, width: f32) -> f32 {
+    if x.rem_euclid(TAU) <= TAU * width.clamp(0.0, 1.0) {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Rises from -1 to 1 over `skew` of the cycle and falls back over the rest, centered so it crosses zero rising at 0 like the triangle
+#[allow(unused)]
+pub fn skewed_triangle(x: f32, skew: f32) -> f32 {
+    let skew = skew.clamp(0.0, 1.0);
+    let position = (x / TAU + 0.5 * skew).rem_euclid(1.0);
+    if position < skew {
+        2.0 * position / skew - 1.0
+    } else {
+        1.0 - 2.0 * (position - skew) / (1.0 - skew)
+    }
+}
+
+#[allow(unused)]
+pub fn sine_to_square(x: f32, shape: f32) -> f32 {
+    let sine = x.sin();
+    sine.signum() * sine.abs().powf(1.0 - shape.clamp(0.0, 1.0))
+}
 
+#[allow(unused)]
+pub fn half_rectified_sine(x: f32) -> f32 {
+    x.sin().max(0.0)
+}
+
+#[allow(unused)]
+pub fn full_rectified_sine(x: f32) -> f32 {
+    x.sin().abs()
+}
+
+#[allow(unused)]
+pub fn trapezoid(x: f32, slope: f32) -> f32 {
+    let slope = slope.clamp(0.0, 1.0);
+    if slope == 0.0 {
+        return square(x);
+    }
+    (triangle(x) / slope).clamp(-1.0, 1.0)
+}
 
 impl WaveForm {
-    pub const ALL: [Self; 4] = [
+    /// Every shape with a function behind it, parametric ones with a typical setting
+    pub const ALL: [Self; 10] = [
         Self::Sine,
         Self::Triangle,
         Self::Square,
-        Self::Saw
+        Self::Saw,
+        Self::Pulse { width: 0.25 },
+        Self::SkewedTriangle { skew: 0.8 },
+        Self::SineToSquare { shape: 0.5 },
+        Self::HalfRectifiedSine,
+        Self::FullRectifiedSine,
+        Self::Trapezoid { slope: 0.5 },
     ];
-    
-    pub fn get_fn(&self) -> fn(f32) -> f32 {
+
+    /// The function of the shape, taking the phase in radians. `Table` has none, so it's an error
+    pub fn get_fn(&self) -> Result<impl Fn(f32) -> f32> {
+        let shape = match *self {
+            Self::Sine => Shape::Sine,
+            Self::Triangle => Shape::Triangle,
+            Self::Square => Shape::Square,
+            Self::Saw => Shape::Saw,
+            Self::Pulse { width } => Shape::Pulse(width),
+            Self::SkewedTriangle { skew } => Shape::SkewedTriangle(skew),
+            Self::SineToSquare { shape } => Shape::SineToSquare(shape),
+            Self::HalfRectifiedSine => Shape::HalfRectifiedSine,
+            Self::FullRectifiedSine => Shape::FullRectifiedSine,
+            Self::Trapezoid { slope } => Shape::Trapezoid(slope),
+            Self::Table => return Err(Error::Synthesis(SynthesisError::SynthesisWaveFormHasNoFunction)),
+        };
+        Ok(move |x| shape.sample(x))
+    }
+
+    /// Name of the shape without its parameters
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Sine => "Sine",
+            Self::Triangle => "Triangle",
+            Self::Square => "Square",
+            Self::Saw => "Saw",
+            Self::Pulse { .. } => "Pulse",
+            Self::SkewedTriangle { .. } => "Skewed Triangle",
+            Self::SineToSquare { .. } => "Sine to Square",
+            Self::HalfRectifiedSine => "Half Rectified Sine",
+            Self::FullRectifiedSine => "Full Rectified Sine",
+            Self::Trapezoid { .. } => "Trapezoid",
+            Self::Table => "WaveTable",
+        }
+    }
+
+    /// The parameter of a parametric shape
+    pub fn get_parameter(&self) -> Option<f32> {
+        match self {
+            Self::Pulse { width } => Some(*width),
+            Self::SkewedTriangle { skew } => Some(*skew),
+            Self::SineToSquare { shape } => Some(*shape),
+            Self::Trapezoid { slope } => Some(*slope),
+            _ => None,
+        }
+    }
+
+    /// The same shape with its parameter set to `parameter`, shapes without one are returned as they are
+    pub fn with_parameter(self, parameter: f32) -> Self {
         match self {
-            Self::Sine => sine,
-            Self::Triangle => triangle,
-            Self::Square => square,
-            Self::Saw => saw,
-            Self::Table => panic!("Invalid get_fn from WaveTable"),
+            Self::Pulse { .. } => Self::Pulse { width: parameter },
+            Self::SkewedTriangle { .. } => Self::SkewedTriangle { skew: parameter },
+            Self::SineToSquare { .. } => Self::SineToSquare { shape: parameter },
+            Self::Trapezoid { .. } => Self::Trapezoid { slope: parameter },
+            waveform => waveform,
+        }
+    }
+}
+
+/// The shapes of [`WaveForm`] that have a function, so sampling one never has to deal with `Table`
+#[derive(Clone, Copy)]
+enum Shape {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+    Pulse(f32),
+    SkewedTriangle(f32),
+    SineToSquare(f32),
+    HalfRectifiedSine,
+    FullRectifiedSine,
+    Trapezoid(f32),
+}
+
+impl Shape {
+    fn sample(self, x: f32) -> f32 {
+        match self {
+            Self::Sine => sine(x),
+            Self::Triangle => triangle(x),
+            Self::Square => square(x),
+            Self::Saw => saw(x),
+            Self::Pulse(width) => pulse(x, width),
+            Self::SkewedTriangle(skew) => skewed_triangle(x, skew),
+            Self::SineToSquare(shape) => sine_to_square(x, shape),
+            Self::HalfRectifiedSine => half_rectified_sine(x),
+            Self::FullRectifiedSine => full_rectified_sine(x),
+            Self::Trapezoid(slope) => trapezoid(x, slope),
         }
     }
 }
 
 impl std::fmt::Display for WaveForm {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f, 
-            "{}",
-            match self {
-                Self::Sine => "Sine",
-                Self::Triangle => "Triangle",
-                Self::Square => "Square",
-                Self::Saw => "Saw",
-                Self::Table => "WaveTable",
-            }
-        )
-    }
-}
\ No newline at end of file
+        match self {
+            Self::Pulse { width } => write!(f, "Pulse {:.0}%", width * 100.0),
+            Self::SkewedTriangle { skew } => write!(f, "Skewed Triangle {:.0}%", skew * 100.0),
+            Self::SineToSquare { shape } => write!(f, "Sine to Square {:.0}%", shape * 100.0),
+            Self::Trapezoid { slope } => write!(f, "Trapezoid {:.0}%", slope * 100.0),
+            _ => write!(f, "{}", self.get_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use crate::{error::SynthesisError, Error};
+    use super::{saw, sine, square, triangle, WaveForm};
+
+    fn samples(waveform: WaveForm) -> Vec<f32> {
+        let function = waveform.get_fn().unwrap();
+        (0..64).map(|i| function(TAU * i as f32 / 64.0)).collect()
+    }
+
+    fn basic(function: fn(f32) -> f32) -> Vec<f32> {
+        (0..64).map(|i| function(TAU * i as f32 / 64.0)).collect()
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            assert!((a - b).abs() < 1e-5, "{a} and {b} at {i}");
+        }
+    }
+
+    #[test]
+    fn parametric_shapes_reach_the_basic_ones() {
+        assert_close(&samples(WaveForm::Pulse { width: 0.5 }), &basic(square));
+        assert_close(&samples(WaveForm::SkewedTriangle { skew: 0.5 }), &basic(triangle));
+        assert_close(&samples(WaveForm::SkewedTriangle { skew: 1.0 }), &basic(saw));
+        assert_close(&samples(WaveForm::SineToSquare { shape: 0.0 }), &basic(sine));
+        assert_close(&samples(WaveForm::SineToSquare { shape: 1.0 })[1..32], &basic(square)[1..32]);
+        assert_close(&samples(WaveForm::Trapezoid { slope: 1.0 }), &basic(triangle));
+        assert_close(&samples(WaveForm::Trapezoid { slope: 0.0 }), &basic(square));
+    }
+
+    #[test]
+    fn parameters_change_the_shape() {
+        // a quarter of the cycle high
+        let pulse = samples(WaveForm::Pulse { width: 0.25 });
+        assert_eq!(pulse.iter().filter(|sample| **sample > 0.0).count(), 17);
+
+        let falling = samples(WaveForm::SkewedTriangle { skew: 0.0 });
+        assert!(falling[1..32].windows(2).all(|pair| pair[1] < pair[0]));
+
+        let trapezoid = samples(WaveForm::Trapezoid { slope: 0.5 });
+        assert!((trapezoid[4] - 0.5).abs() < 1e-5 && trapezoid[16] == 1.0);
+
+        let half = WaveForm::HalfRectifiedSine.get_fn().unwrap();
+        let full = WaveForm::FullRectifiedSine.get_fn().unwrap();
+        assert_eq!((half(PI / 2.0), half(1.5 * PI), full(1.5 * PI)), (1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn describes_itself() {
+        assert_eq!(WaveForm::Pulse { width: 0.1 }.to_string(), "Pulse 10%");
+        assert_eq!(WaveForm::Saw.to_string(), "Saw");
+        assert_eq!(WaveForm::Trapezoid { slope: 0.5 }.get_name(), "Trapezoid");
+        assert_eq!(WaveForm::SineToSquare { shape: 0.5 }.with_parameter(0.75), WaveForm::SineToSquare { shape: 0.75 });
+        assert_eq!(WaveForm::Pulse { width: 0.3 }.get_parameter(), Some(0.3));
+        assert_eq!(WaveForm::Sine.with_parameter(0.3).get_parameter(), None);
+        assert!(matches!(WaveForm::Table.get_fn(), Err(Error::Synthesis(SynthesisError::SynthesisWaveFormHasNoFunction))));
+    }
+}
@@ -1,40 +1,132 @@
 use std::f32::consts::TAU;
 
-use crate::math::lerp;
+use crate::{math::lerp, Result};
 
-use super::waveforms::WaveForm;
+use super::{fft::{real_fft, real_ifft, Complex}, interpolation::Interpolation, spectrum::Spectrum, waveforms::WaveForm};
 
+/// Table length with enough room for the harmonics of low notes
+pub const DEFAULT_TABLE_LENGTH: usize = 2048;
+
+/// One or more frames, each a single cycle of a waveform, stored as a set of band-limited versions one octave apart.
+/// Level `k` only keeps the harmonics up to `len / 2 >> k`, so high notes can read a level without harmonics above Nyquist.
+/// A position in [0, 1] scans through the frames, blending the two closest ones
 #[derive(Clone, Debug)]
 pub struct WaveTable {
-    table: Vec<f32>,
+    frames: Vec<Vec<Vec<f32>>>, //band-limited levels of every frame
     waveform: WaveForm,
-    inverse_step: f32,
+    interpolation: Interpolation,
+}
+
+/// Which levels of a [`WaveTable`] to read for a pitch and how to blend them, see [`WaveTable::mip_level`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MipLevel {
+    lower: usize,
+    blend: f32, //amount of level lower + 1 mixed in
+}
+
+impl MipLevel {
+    pub fn get_lower(&self) -> usize {
+        self.lower
+    }
+
+    pub fn get_blend(&self) -> f32 {
+        self.blend
+    }
+}
+
+/// Removes every harmonic above `max_harmonic` from `spectrum`, keeping the offset
+fn band_limit(spectrum: &[Complex], max_harmonic: usize) -> Vec<f32> {
+    let length = spectrum.len();
+    let mut limited = vec![Complex::ZERO; length];
+    limited[0] = spectrum[0];
+    for harmonic in 1..=max_harmonic.min((length - 1) / 2) {
+        limited[harmonic] = spectrum[harmonic];
+        limited[length - harmonic] = spectrum[length - harmonic];
+    }
+    real_ifft(&limited)
+}
+
+/// Band-limited versions of `table`, one octave apart down to a single harmonic
+fn build_levels(table: Vec<f32>) -> Vec<Vec<f32>> {
+    let length = table.len();
+    if length < 4 {
+        return vec![table];
+    }
+    let spectrum = real_fft(&table);
+    let mut levels = vec![band_limit(&spectrum, length / 2 - 1)];
+    let mut max_harmonic = length / 4;
+    while max_harmonic >= 1 {
+        levels.push(band_limit(&spectrum, max_harmonic));
+        max_harmonic /= 2;
+    }
+    levels
 }
 
 impl WaveTable {
     pub fn new(table: Vec<f32>, waveform: WaveForm) -> Self {
         Self {
             waveform,
-            inverse_step: (table.len() as f32)/TAU,
-            table,
+            interpolation: Interpolation::default(),
+            frames: vec![build_levels(table)],
         }
     }
 
-    pub fn from_waveform(waveform: WaveForm, length: usize) -> Self {
-        let mut table = Vec::with_capacity(length);
+    /// A table scanning through `frames` in order, will panic if there are none or their lengths differ
+    pub fn from_frames(frames: Vec<Vec<f32>>) -> Self {
+        assert!(!frames.is_empty(), "a wavetable needs at least one frame");
+        let length = frames[0].len();
+        assert!(frames.iter().all(|frame| frame.len() == length), "every frame has to be {length} samples long");
+        Self {
+            waveform: WaveForm::Table,
+            interpolation: Interpolation::default(),
+            frames: frames.into_iter().map(build_levels).collect(),
+        }
+    }
+
+    /// Like [`WaveTable::from_fn`] for `frame_count` frames, `function` gets the position of the frame in [0, 1] and the phase in radians
+    pub fn from_frames_fn<F>(function: F, length: usize, frame_count: usize) -> Self
+        where F: Fn(f32, f32) -> f32,
+    {
         let step = TAU / (length as f32);
-        let function = waveform.get_fn();
+        let last = frame_count.saturating_sub(1).max(1) as f32;
+        let frames = (0..frame_count).map(|frame| {
+            let position = frame as f32 / last;
+            (0..length).map(|i| function(position, (i as f32) * step)).collect()
+        }).collect();
+        Self::from_frames(frames)
+    }
 
-        for i in 0..length {
-            table.push(function((i as f32) * step));
-        }
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
 
+    pub fn get_interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
 
-        Self {
-            waveform,
-            table,
-            inverse_step: (length as f32)/TAU,
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    fn lookup_level(&self, frame: usize, level: usize, phase: f64) -> f32 {
+        let table = &self.frames[frame][level];
+        self.interpolation.read(table, phase * table.len() as f64)
+    }
+
+    fn lookup_frame(&self, frame: usize, phase: f64, level: MipLevel) -> f32 {
+        let sample = self.lookup_level(frame, level.lower, phase);
+        if level.blend == 0.0 {
+            return sample;
         }
+        lerp(sample, self.lookup_level(frame, level.lower + 1, phase), level.blend)
+    }
+
+    /// A table of one of the built in shapes, which can't be [`WaveForm::Table`]
+    pub fn from_waveform(waveform: WaveForm, length: usize) -> Result<Self> {
+        let mut wavetable = Self::from_fn(waveform.get_fn()?, length);
+        wavetable.waveform = waveform;
+        Ok(wavetable)
     }
 
     pub fn from_fn<F>(function: F, length: usize) -> Self
@@ -47,28 +139,193 @@ impl WaveTable {
             table.push(function((i as f32) * step));
         }
 
-        Self {
-            waveform: WaveForm::Table,
-            table,
-            inverse_step: (length as f32)/TAU,
-        }
+        Self::new(table, WaveForm::Table)
     }
 
+    /// A frame for every spectrum, resynthesized into `length` samples each
+    pub fn from_spectra(spectra: &[Spectrum], length: usize) -> Self {
+        Self::from_frames(spectra.iter().map(|spectrum| spectrum.synthesize(length)).collect())
+    }
+
+    /// The harmonics of `frame`
+    pub fn analyze(&self, frame: usize) -> Spectrum {
+        Spectrum::analyze(self.get_frame_level(frame, 0))
+    }
+
+    /// A copy with `edit` applied to the spectrum of every frame, keeping the length and interpolation
+    pub fn edit_spectra<F>(&self, mut edit: F) -> Self
+        where F: FnMut(&mut Spectrum),
+    {
+        let spectra: Vec<Spectrum> = (0..self.frame_count()).map(|frame| {
+            let mut spectrum = self.analyze(frame);
+            edit(&mut spectrum);
+            spectrum
+        }).collect();
+        Self::from_spectra(&spectra, self.len()).with_interpolation(self.interpolation)
+    }
+
+    /// Reads the fullest level of the first frame at `phase` radians
     pub fn lookup(&self, phase: f32) -> f32 {
-        let phase = phase % TAU;
-        let index = phase * self.inverse_step;
+        let phase = phase.rem_euclid(TAU) / TAU;
+        self.lookup_normalized(phase as f64)
+    }
 
-        let index_trunc = index as usize;
-        let next_index_trunc = (index_trunc+1) % self.len();
+    /// Like [`WaveTable::lookup`] with the phase in cycles instead of radians, `phase` has to be in [0, 1)
+    pub fn lookup_normalized(&self, phase: f64) -> f32 {
+        self.lookup_level(0, 0, phase)
+    }
 
-        lerp(self.table[index_trunc], self.table[next_index_trunc], index-(index_trunc as f32))
+    /// The levels to read when the phase moves `increment` cycles per sample.
+    /// Blends between the two fullest levels that have no harmonics above Nyquist, so timbre changes smoothly with pitch
+    pub fn mip_level(&self, increment: f64) -> MipLevel {
+        let last = self.level_count() - 1;
+        // level k is free of aliasing below 2^(k - 1) / len cycles per sample
+        let position = (2.0 * self.len() as f64 * increment.abs()).log2();
+        if position.is_nan() || position <= 0.0 {
+            return MipLevel::default();
+        }
+        let lower = position.floor() as usize;
+        if lower >= last {
+            return MipLevel { lower: last, blend: 0.0 };
+        }
+        MipLevel {
+            lower,
+            blend: (position - position.floor()) as f32,
+        }
     }
-    
+
+    /// Reads the levels picked by [`WaveTable::mip_level`] at `phase` cycles, in [0, 1),
+    /// from the frames around `position`, which is clamped to [0, 1]
+    pub fn lookup_band_limited(&self, phase: f64, level: MipLevel, position: f32) -> f32 {
+        let last = self.frames.len() - 1;
+        let position = position.clamp(0.0, 1.0) * last as f32;
+        let frame = position as usize;
+        let sample = self.lookup_frame(frame, phase, level);
+        if frame >= last || position == frame as f32 {
+            return sample;
+        }
+        lerp(sample, self.lookup_frame(frame + 1, phase, level), position - frame as f32)
+    }
+
     pub fn get_waveform(&self) -> WaveForm {
         self.waveform
     }
 
+    /// Samples in every frame
     pub fn len(&self) -> usize {
-        self.table.len()
+        self.frames[0][0].len()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.frames[0].len()
+    }
+
+    /// A level of the first frame
+    pub fn get_level(&self, level: usize) -> &[f32] {
+        &self.frames[0][level]
+    }
+
+    pub fn get_frame_level(&self, frame: usize, level: usize) -> &[f32] {
+        &self.frames[frame][level]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use crate::synthesis::{fft::real_fft, interpolation::Interpolation, spectrum::Spectrum, waveforms::WaveForm};
+    use super::{MipLevel, WaveTable};
+
+    /// Highest harmonic with more than a negligible amplitude
+    fn highest_harmonic(table: &[f32]) -> usize {
+        let spectrum = real_fft(table);
+        (1..table.len() / 2 + 1).rev()
+            .find(|harmonic| spectrum[*harmonic].magnitude() > 1e-3 * table.len() as f64)
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn levels_halve_the_harmonics() {
+        let wavetable = WaveTable::from_waveform(WaveForm::Saw, 256).unwrap();
+        assert_eq!(wavetable.level_count(), 8);
+        assert_eq!(highest_harmonic(wavetable.get_level(0)), 127);
+        for level in 1..wavetable.level_count() {
+            assert_eq!(highest_harmonic(wavetable.get_level(level)), 128 >> level);
+            assert_eq!(wavetable.get_level(level).len(), 256);
+        }
+    }
+
+    #[test]
+    fn picked_levels_stay_below_nyquist() {
+        let wavetable = WaveTable::from_waveform(WaveForm::Square, 2048).unwrap();
+        for i in 1..1000 {
+            let increment = i as f64 * 0.0005;
+            // the lower level is the fuller one of the two that get blended
+            let level = wavetable.mip_level(increment).get_lower();
+            let harmonic = highest_harmonic(wavetable.get_level(level)) as f64;
+            assert!(harmonic * increment < 0.5, "increment {increment} reads {harmonic} harmonics");
+        }
+        assert_eq!(wavetable.mip_level(0.0).get_lower(), 0);
+        assert_eq!(wavetable.mip_level(-0.01), wavetable.mip_level(0.01));
+    }
+
+    #[test]
+    fn sine_survives_band_limiting() {
+        let wavetable = WaveTable::from_waveform(WaveForm::Sine, 128).unwrap();
+        for level in 0..wavetable.level_count() {
+            for (i, sample) in wavetable.get_level(level).iter().enumerate() {
+                let expected = (TAU * i as f32 / 128.0).sin();
+                assert!((sample - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn position_morphs_between_frames() {
+        // sine, silence and an inverted sine
+        let wavetable = WaveTable::from_frames_fn(|position, phase| (1.0 - 2.0 * position) * phase.sin(), 256, 3);
+        assert_eq!(wavetable.frame_count(), 3);
+        assert_eq!(wavetable.len(), 256);
+
+        let level = MipLevel::default();
+        for (position, gain) in [(0.0, 1.0), (0.25, 0.5), (0.5, 0.0), (0.9, -0.8), (1.0, -1.0), (-3.0, 1.0), (7.0, -1.0)] {
+            for i in 0..64 {
+                let phase = i as f64 / 64.0;
+                let expected = gain * (TAU * phase as f32).sin();
+                let sample = wavetable.lookup_band_limited(phase, level, position);
+                assert!((sample - expected).abs() < 1e-4, "position {position}: expected {expected}, got {sample}");
+            }
+        }
+    }
+
+    #[test]
+    fn frames_are_band_limited_separately() {
+        let saw = WaveTable::from_waveform(WaveForm::Saw, 256).unwrap();
+        let sine = WaveTable::from_waveform(WaveForm::Sine, 256).unwrap();
+        let wavetable = WaveTable::from_frames(vec![sine.get_level(0).to_vec(), saw.get_level(0).to_vec()]);
+        for level in 0..wavetable.level_count() {
+            assert_eq!(highest_harmonic(wavetable.get_frame_level(0, level)), 1);
+            assert_eq!(highest_harmonic(wavetable.get_frame_level(1, level)), highest_harmonic(saw.get_level(level)));
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn spectral_edits_keep_the_table_shape() {
+        let saw = WaveTable::from_spectra(&[Spectrum::from_amplitudes(&(1..=200).map(|number| 1.0 / number as f32).collect::<Vec<_>>())], 256);
+        assert_eq!((saw.frame_count(), saw.len()), (1, 256));
+        assert_eq!(highest_harmonic(saw.get_level(0)), 127);
+
+        let square = saw.with_interpolation(Interpolation::Sinc).edit_spectra(Spectrum::remove_even_harmonics);
+        assert_eq!(square.get_interpolation(), Interpolation::Sinc);
+        let spectrum = square.analyze(0);
+        for (index, harmonic) in spectrum.get_harmonics().iter().enumerate() {
+            let expected = if index % 2 == 0 { 1.0 / (index + 1) as f32 } else { 0.0 };
+            assert!((harmonic.amplitude - expected).abs() < 1e-5, "harmonic {}: {}", index + 1, harmonic.amplitude);
+        }
+    }
+}